@@ -1,4 +1,8 @@
 fn main() {
+    for cfg in ["rlua_lua54", "rlua_lua53", "rlua_lua51", "rlua_luajit"] {
+        println!("cargo:rustc-check-cfg=cfg({})", cfg);
+    }
+
     let mut lua_version_features = 0;
     #[cfg(feature = "builtin-lua54")]
     {
@@ -43,9 +47,56 @@ fn main() {
         lua_version_features += 1;
     }
 
+    #[cfg(all(
+        feature = "lua-compat-mathlib",
+        any(feature = "builtin-lua53", feature = "builtin-lua54")
+    ))]
+    build_mathlib();
+
     if lua_version_features < 1 {
         panic!("No Lua version specified.  Please enable one of the features. use --no-default-features to disable default lua feature.");
     } else if lua_version_features > 1 {
         panic!("Cannot enable more than one Lua interpreter feature.");
     }
 }
+
+// Builds the Lua math library without its C compat functions, as `rlua_luaopen_math`.
+// `src/mathlib.rs` provides `luaopen_math`, which adds the Rust ones.
+#[cfg(all(
+    feature = "lua-compat-mathlib",
+    any(feature = "builtin-lua53", feature = "builtin-lua54")
+))]
+fn build_mathlib() {
+    use std::{env, path::Path};
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=mathlib");
+
+    let source = if cfg!(feature = "builtin-lua54") {
+        Path::new("mathlib/lua-5.4")
+    } else {
+        Path::new("mathlib/lua-5.3")
+    };
+    // The headers the builtin Lua was built with, from mlua-sys.
+    let include = env::var_os("DEP_LUA_INCLUDE").expect("mlua-sys didn't report its headers");
+
+    let mut build = cc::Build::new();
+    let target = env::var("TARGET").unwrap();
+    if target.contains("linux") || target.ends_with("bsd") {
+        build.define("LUA_USE_LINUX", None);
+    } else if target.contains("apple-darwin") {
+        build.define("LUA_USE_MACOSX", None);
+    } else if target.contains("windows") {
+        build.define("LUA_USE_WINDOWS", None);
+    } else {
+        build.define("LUA_USE_POSIX", None);
+    }
+
+    build
+        .include(source)
+        .include(include)
+        .define("luaopen_math", "rlua_luaopen_math")
+        .warnings(false)
+        .file(source.join("lmathlib.c"))
+        .compile("rlua_mathlib");
+}
@@ -0,0 +1,125 @@
+//! Procedural macros for rlua, re-exported from the `rlua` crate.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::{parse_macro_input, Error, LitStr, Token};
+
+/// Embeds every `.lua` file below a directory into the binary, as a `bundle::Bundle`.
+///
+/// Called as `include_lua!($crate, "dir")` by rlua's own `include_lua!`, which passes the
+/// path to the `rlua` crate, however it's named where the macro is used.  The directory is
+/// relative to the one containing the crate's `Cargo.toml`.  See the `rlua` documentation
+/// for details.
+#[proc_macro]
+pub fn include_lua(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as Input);
+    match expand(&input.krate, &input.dir) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+struct Input {
+    krate: proc_macro2::TokenStream,
+    dir: LitStr,
+}
+
+impl Parse for Input {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        // `$crate` reaches the macro as a single identifier, but any path works.
+        let mut krate = proc_macro2::TokenStream::new();
+        while !input.peek(Token![,]) {
+            krate.extend(Some(input.parse::<proc_macro2::TokenTree>()?));
+        }
+        input.parse::<Token![,]>()?;
+        let dir = input.parse()?;
+        input.parse::<Option<Token![,]>>()?;
+        Ok(Input { krate, dir })
+    }
+}
+
+fn expand(krate: &proc_macro2::TokenStream, dir: &LitStr) -> syn::Result<proc_macro2::TokenStream> {
+    let manifest_dir = std::env::var_os("CARGO_MANIFEST_DIR")
+        .ok_or_else(|| Error::new(Span::call_site(), "CARGO_MANIFEST_DIR is not set"))?;
+    let root = Path::new(&manifest_dir).join(dir.value());
+
+    let mut files = Vec::new();
+    find_lua_files(&root, &mut files).map_err(|err| {
+        Error::new(
+            dir.span(),
+            format!("cannot read {}: {}", root.display(), err),
+        )
+    })?;
+    files.sort();
+
+    let mut modules = Vec::new();
+    for file in &files {
+        let relative = file.strip_prefix(&root).unwrap();
+        let chunk_name = Path::new(&dir.value()).join(relative);
+        let chunk_name = chunk_name.to_string_lossy().replace('\\', "/");
+        let name = module_name(relative);
+
+        #[cfg(feature = "check")]
+        if let Err(message) = check_syntax(file, &chunk_name) {
+            let message = format!("syntax error in Lua module {}", message);
+            return Ok(quote::quote_spanned! {dir.span()=> ::core::compile_error!(#message) });
+        }
+
+        let full_path = file.to_string_lossy().into_owned();
+        modules.push(quote! {
+            (#name, #chunk_name, include_bytes!(#full_path))
+        });
+    }
+
+    Ok(quote! {
+        #krate::bundle::Bundle::new(&[#(#modules),*])
+    })
+}
+
+fn find_lua_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_lua_files(&path, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "lua") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// `a/b.lua` becomes `a.b`, and `a/init.lua` becomes `a`.
+fn module_name(relative: &Path) -> String {
+    let mut parts: Vec<_> = relative
+        .with_extension("")
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    if parts.len() > 1 && parts.last().is_some_and(|last| last == "init") {
+        parts.pop();
+    }
+    parts.join(".")
+}
+
+// Parses a file with the Lua version rlua is built with, returning the error message, which
+// starts with the file's path and the line.
+#[cfg(feature = "check")]
+fn check_syntax(file: &Path, chunk_name: &str) -> Result<(), String> {
+    let source = fs::read(file).map_err(|err| format!("{}: {}", chunk_name, err))?;
+    let lua = mlua::Lua::new();
+    let result = lua
+        .load(&source)
+        .set_name(format!("@{}", chunk_name))
+        .set_mode(mlua::ChunkMode::Text)
+        .into_function();
+    match result {
+        Ok(_) => Ok(()),
+        Err(mlua::Error::SyntaxError { message, .. }) => Err(message),
+        Err(err) => Err(format!("{}: {}", chunk_name, err)),
+    }
+}
@@ -0,0 +1,753 @@
+//! Rewrites Rust sources using the rlua 0.20 compatibility helpers into plain `mlua`.
+//!
+//! `rlua-migrate <path>...` rewrites the `.rs` files under each path in place, and then
+//! lists the compat usages it could not rewrite.  `rlua-migrate --check <path>...` only
+//! lists the compat usages, including those which would be rewritten, and exits with a
+//! non-zero status if there are any.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use proc_macro2::{LineColumn, Span, TokenStream, TokenTree};
+use syn::spanned::Spanned;
+use syn::visit::{self, Visit};
+use syn::{
+    Expr, ExprClosure, ExprMethodCall, Ident, ItemExternCrate, ItemUse, Pat, Path as SynPath,
+    ReturnType, UseTree,
+};
+
+const USAGE: &str = "usage: rlua-migrate [--check] <path>...";
+
+// Names which only exist in rlua, with a hint for replacing them.
+const RLUA_ONLY: &[(&str, &str)] = &[
+    ("Context", "use `&Lua` instead"),
+    ("LuaContext", "use `&Lua` instead"),
+    ("ContextChunk", "use `Chunk` instead"),
+    ("LuaContextChunk", "use `Chunk` instead"),
+    ("InitFlags", "use `StdLib` and `LuaOptions` instead"),
+    ("LuaInitFlags", "use `StdLib` and `LuaOptions` instead"),
+    ("RluaCompat", "call the methods on `Lua` directly"),
+    ("ScopeCompat", "use `Scope::create_userdata` instead"),
+    ("ChunkCompat", "use `Chunk::into_function` instead"),
+    (
+        "UserDataMethodsCompat",
+        "register callbacks taking `&Lua` instead",
+    ),
+    (
+        "UserDataFieldsCompat",
+        "register callbacks taking `&Lua` instead",
+    ),
+    ("ErrorExt", "keep the rlua dependency for it"),
+    ("include_lua", "keep the rlua dependency for it"),
+    ("LuaDeterministicExt", "keep the rlua dependency for it"),
+    ("LuaInterruptExt", "keep the rlua dependency for it"),
+    ("LuaLimitsExt", "keep the rlua dependency for it"),
+    ("LuaMemoryExt", "keep the rlua dependency for it"),
+    ("LuaOutputExt", "keep the rlua dependency for it"),
+    ("LuaResolverExt", "keep the rlua dependency for it"),
+];
+
+// Modules which only exist in rlua.
+const RLUA_MODULES: &[&str] = &[
+    "bundle",
+    "bytecode",
+    "capability",
+    "deterministic",
+    "interrupt",
+    "limits",
+    "memory",
+    "modules",
+    "output",
+    "resolver",
+    "sandbox",
+];
+
+// Methods added by the rlua compat traits which can't be rewritten mechanically.
+const COMPAT_METHODS: &[(&str, &str)] = &[
+    (
+        "unsafe_new_with_flags",
+        "use `Lua::unsafe_new_with` instead",
+    ),
+    ("install_load_wrappers", "has no mlua equivalent"),
+    ("load_compat_mathlib", "has no mlua equivalent"),
+    (
+        "create_static_userdata",
+        "use `Scope::create_userdata` instead",
+    ),
+    (
+        "into_function_allow_binary",
+        "use `Chunk::into_function` instead",
+    ),
+];
+
+// Methods from `RluaCompat` itself, which keep the trait import alive.
+const RLUA_COMPAT_METHODS: &[&str] = &[
+    "context",
+    "unsafe_new_with_flags",
+    "install_load_wrappers",
+    "load_compat_mathlib",
+];
+
+const RENAMES: &[(&str, &str)] = &[
+    ("ToLua", "IntoLua"),
+    ("ToLuaCompat", "IntoLua"),
+    ("ToLuaMulti", "IntoLuaMulti"),
+    ("ToLuaMultiCompat", "IntoLuaMulti"),
+    ("FromLuaMultiCompat", "FromLuaMulti"),
+    ("to_lua", "into_lua"),
+    ("to_lua_multi", "into_lua_multi"),
+];
+
+// Compat traits which turn into an import of their mlua counterpart, along with the names
+// which already import that counterpart.
+const COMPAT_TRAITS: &[(&str, &str, &[&str])] = &[
+    ("ToLuaCompat", "IntoLua", &["ToLua", "IntoLua"]),
+    (
+        "ToLuaMultiCompat",
+        "IntoLuaMulti",
+        &["ToLuaMulti", "IntoLuaMulti"],
+    ),
+    ("FromLuaMultiCompat", "FromLuaMulti", &["FromLuaMulti"]),
+];
+
+fn main() {
+    let mut check = false;
+    let mut paths = Vec::new();
+    for arg in std::env::args_os().skip(1) {
+        if arg == "--check" {
+            check = true;
+        } else if arg == "--help" || arg == "-h" {
+            println!("{}", USAGE);
+            return;
+        } else {
+            paths.push(PathBuf::from(arg));
+        }
+    }
+    if paths.is_empty() {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    let mut files = Vec::new();
+    for path in &paths {
+        if let Err(err) = collect_files(path, &mut files) {
+            eprintln!("{}: {}", path.display(), err);
+            process::exit(2);
+        }
+    }
+
+    let mut failed = false;
+    let mut remaining = 0;
+    let mut rewritten = 0;
+    for file in &files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("{}: {}", file.display(), err);
+                failed = true;
+                continue;
+            }
+        };
+        let migration = match migrate(&source) {
+            Ok(migration) => migration,
+            Err(err) => {
+                eprintln!("{}: {}", file.display(), err);
+                failed = true;
+                continue;
+            }
+        };
+
+        for finding in &migration.findings {
+            if check || !finding.fixable {
+                println!(
+                    "{}:{}:{}: {}",
+                    file.display(),
+                    finding.at.line,
+                    finding.at.column + 1,
+                    finding.message
+                );
+                remaining += 1;
+            }
+        }
+        if !check && migration.output != source {
+            if let Err(err) = fs::write(file, &migration.output) {
+                eprintln!("{}: {}", file.display(), err);
+                failed = true;
+                continue;
+            }
+            rewritten += 1;
+        }
+    }
+
+    if !check && rewritten > 0 {
+        eprintln!(
+            "rewrote {} file(s); run `cargo fmt`, and add `mlua` with the Lua version \
+             features previously enabled on rlua to Cargo.toml",
+            rewritten
+        );
+    }
+    if failed {
+        process::exit(2);
+    }
+    if check && remaining > 0 {
+        process::exit(1);
+    }
+}
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_owned());
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    entries.sort();
+    for entry in entries {
+        let name = entry.file_name().unwrap_or_default().to_string_lossy();
+        if entry.is_dir() {
+            if name != "target" && !name.starts_with('.') {
+                collect_files(&entry, files)?;
+            }
+        } else if name.ends_with(".rs") {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+struct Migration {
+    output: String,
+    findings: Vec<Finding>,
+}
+
+struct Finding {
+    at: LineColumn,
+    message: String,
+    fixable: bool,
+}
+
+struct Edit {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+fn migrate(source: &str) -> Result<Migration, String> {
+    let file = syn::parse_file(source).map_err(|err| {
+        let at = err.span().start();
+        format!("{}:{}: {}", at.line, at.column + 1, err)
+    })?;
+
+    let mut migrator = Migrator::new(source);
+    migrator.visit_file(&file);
+    migrator.finish();
+
+    let Migrator {
+        mut edits,
+        mut findings,
+        ..
+    } = migrator;
+    edits.sort_by_key(|edit| (edit.start, edit.end));
+    findings.sort_by_key(|finding| (finding.at.line, finding.at.column));
+
+    let mut output = String::with_capacity(source.len());
+    let mut pos = 0;
+    for edit in &edits {
+        if edit.start < pos {
+            return Err("overlapping rewrites; file left unchanged".to_owned());
+        }
+        output.push_str(&source[pos..edit.start]);
+        output.push_str(&edit.text);
+        pos = edit.end;
+    }
+    output.push_str(&source[pos..]);
+
+    Ok(Migration { output, findings })
+}
+
+// A `use rlua::...` item, whose rewriting is decided once the whole file has been seen.
+struct RluaUse {
+    item: (usize, usize),
+    root: Span,
+    // Leaves directly under `rlua::`, with their byte ranges.
+    leaves: Vec<(String, (usize, usize))>,
+    // For `use rlua::{...}`, the range of each element, so that elements can be removed.
+    group: Option<Vec<(usize, usize)>>,
+    glob: bool,
+}
+
+struct Migrator<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+    edits: Vec<Edit>,
+    findings: Vec<Finding>,
+    uses: Vec<RluaUse>,
+    extern_crates: Vec<Span>,
+    // Local names which refer to rlua-only items.
+    rlua_names: HashSet<String>,
+    needs_rlua_compat: bool,
+    // Whether some path in the file has to keep referring to `rlua`.
+    rlua_kept: bool,
+    // Edits which start an inlined `context` closure, with the name they bind.
+    context_bindings: Vec<(usize, String)>,
+}
+
+impl<'a> Migrator<'a> {
+    fn new(source: &'a str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+        Migrator {
+            source,
+            line_starts,
+            edits: Vec::new(),
+            findings: Vec::new(),
+            uses: Vec::new(),
+            extern_crates: Vec::new(),
+            rlua_names: HashSet::new(),
+            needs_rlua_compat: false,
+            rlua_kept: false,
+            context_bindings: Vec::new(),
+        }
+    }
+
+    fn offset(&self, at: LineColumn) -> usize {
+        let line_start = self.line_starts[at.line - 1];
+        self.source[line_start..]
+            .char_indices()
+            .nth(at.column)
+            .map(|(i, _)| line_start + i)
+            .unwrap_or(self.source.len())
+    }
+
+    fn range(&self, span: Span) -> (usize, usize) {
+        (self.offset(span.start()), self.offset(span.end()))
+    }
+
+    fn text(&self, span: Span) -> &'a str {
+        let (start, end) = self.range(span);
+        &self.source[start..end]
+    }
+
+    fn edit(&mut self, (start, end): (usize, usize), text: impl Into<String>) {
+        self.edits.push(Edit {
+            start,
+            end,
+            text: text.into(),
+        });
+    }
+
+    fn report(&mut self, span: Span, message: String, fixable: bool) {
+        self.report_at(span.start(), message, fixable);
+    }
+
+    fn report_at(&mut self, at: LineColumn, message: String, fixable: bool) {
+        self.findings.push(Finding {
+            at,
+            message,
+            fixable,
+        });
+    }
+
+    fn rename(&mut self, ident: &Ident) {
+        let name = ident.to_string();
+        if let Some((_, new)) = RENAMES.iter().find(|(old, _)| *old == name) {
+            self.edit(self.range(ident.span()), *new);
+            self.report(
+                ident.span(),
+                format!("`{}` can be renamed to `{}`", name, new),
+                true,
+            );
+        }
+    }
+
+    // Rewrites `lua.context(|ctx| body)` into `{ let ctx: &Lua = &lua; body }`, or into
+    // `{ let ctx: &Lua = &lua; (|| body)() }` where the body relies on being a closure.
+    fn rewrite_context(&mut self, call: &ExprMethodCall, closure: &ExprClosure) {
+        let pat = match &closure.inputs[0] {
+            Pat::Type(pat) => &*pat.pat,
+            pat => pat,
+        };
+        let receiver = self.range(call.receiver.span());
+        let close_paren = self.range(call.paren_token.span.close());
+
+        // The binding is filled in by `finish`, once it is known whether the file still
+        // refers to `rlua`.
+        let binding = self.text(pat.span()).to_owned();
+        self.context_bindings.push((self.edits.len(), binding));
+        self.edit((receiver.0, receiver.0), "");
+
+        let body = &*closure.body;
+        let typed = matches!(closure.output, ReturnType::Type(..));
+        let inline = escapes_closure(body) || typed;
+        match body {
+            Expr::Block(block) if !inline && block.label.is_none() && block.attrs.is_empty() => {
+                let braces = block.block.brace_token.span;
+                self.edit((receiver.1, self.range(braces.open()).1), ";");
+                self.edit((self.range(braces.close()).0, close_paren.1), "}");
+            }
+            _ if inline => {
+                let mut closure_head = String::from("; (");
+                if closure.capture.is_some() {
+                    closure_head.push_str("move ");
+                }
+                closure_head.push_str("||");
+                if typed {
+                    closure_head.push(' ');
+                    closure_head.push_str(self.text(closure.output.span()));
+                }
+                closure_head.push(' ');
+                let body = self.range(body.span());
+                self.edit((receiver.1, body.0), closure_head);
+                self.edit((body.1, close_paren.1), ")() }");
+            }
+            _ => {
+                let body = self.range(body.span());
+                self.edit((receiver.1, body.0), "; ");
+                self.edit((body.1, close_paren.1), " }");
+            }
+        }
+    }
+
+    fn visit_rlua_use(&mut self, item: &ItemUse, tree: &UseTree, root: Span) {
+        let mut rlua_use = RluaUse {
+            item: self.range(item.span()),
+            root,
+            leaves: Vec::new(),
+            group: None,
+            glob: false,
+        };
+        let elements = match tree {
+            UseTree::Group(group) => {
+                rlua_use.group = Some(group.items.iter().map(|t| self.range(t.span())).collect());
+                group.items.iter().collect()
+            }
+            tree => vec![tree],
+        };
+        for tree in elements {
+            let name = match tree {
+                UseTree::Name(name) => Some((&name.ident, &name.ident)),
+                UseTree::Rename(rename) => Some((&rename.ident, &rename.rename)),
+                UseTree::Glob(_) => {
+                    rlua_use.glob = true;
+                    for (name, _) in RLUA_ONLY {
+                        self.rlua_names.insert(name.to_string());
+                    }
+                    None
+                }
+                UseTree::Path(path) if RLUA_MODULES.iter().any(|n| path.ident == n) => {
+                    Some((&path.ident, &path.ident))
+                }
+                UseTree::Path(path) if path.ident == "prelude" => {
+                    if let UseTree::Glob(_) = *path.tree {
+                        rlua_use.glob = true;
+                        for name in ["LuaContext", "LuaInitFlags", "RluaCompat"] {
+                            self.rlua_names.insert(name.to_owned());
+                        }
+                    }
+                    None
+                }
+                _ => None,
+            };
+            if let Some((ident, local)) = name {
+                if RLUA_ONLY.iter().any(|(n, _)| ident == n) {
+                    self.rlua_names.insert(local.to_string());
+                }
+                rlua_use
+                    .leaves
+                    .push((ident.to_string(), self.range(tree.span())));
+            }
+        }
+        self.uses.push(rlua_use);
+    }
+
+    // Decides what happens to the `use rlua::...` items and `extern crate rlua` once the
+    // whole file has been visited.
+    fn finish(&mut self) {
+        let needs_rlua_compat = self.needs_rlua_compat;
+        for rlua_use in std::mem::take(&mut self.uses) {
+            // Compat traits turn into an import of the mlua trait, unless there is one already.
+            let mut kept_rlua_only = false;
+            let mut removed = Vec::new();
+            for (name, range) in &rlua_use.leaves {
+                let compat = COMPAT_TRAITS.iter().find(|(n, _, _)| n == name);
+                if let Some((_, target, existing)) = compat {
+                    let has_target = rlua_use
+                        .leaves
+                        .iter()
+                        .any(|(n, _)| existing.contains(&n.as_str()));
+                    if has_target {
+                        removed.push(*range);
+                    } else {
+                        let ident = (range.0, range.0 + name.len());
+                        self.edit(ident, *target);
+                        let message = format!("`{}` can be renamed to `{}`", name, target);
+                        self.report_at(self.position(range.0), message, true);
+                    }
+                } else if name == "RluaCompat" && !needs_rlua_compat {
+                    removed.push(*range);
+                } else if let Some(hint) = rlua_only_hint(name) {
+                    kept_rlua_only = true;
+                    let at = self.position(range.0);
+                    let message = format!("`{}` is only provided by rlua; {}", name, hint);
+                    self.report_at(at, message, false);
+                }
+            }
+
+            match &rlua_use.group {
+                Some(elements) if removed.len() == elements.len() => {
+                    self.remove_item(rlua_use.item);
+                    continue;
+                }
+                Some(elements) => self.remove_from_group(elements, &removed),
+                None if !removed.is_empty() => {
+                    self.remove_item(rlua_use.item);
+                    continue;
+                }
+                None => {}
+            }
+
+            // A glob keeps pointing at rlua while it still provides names used in the file.
+            let glob_needed = rlua_use.glob && self.findings.iter().any(|f| !f.fixable);
+            if kept_rlua_only || glob_needed {
+                self.rlua_kept = true;
+            } else {
+                self.edit(self.range(rlua_use.root), "mlua");
+                self.report(
+                    rlua_use.root,
+                    "`rlua` can be replaced by `mlua`".to_owned(),
+                    true,
+                );
+            }
+        }
+
+        for span in std::mem::take(&mut self.extern_crates) {
+            if !self.rlua_kept {
+                self.edit(self.range(span), "mlua");
+                self.report(span, "`rlua` can be replaced by `mlua`".to_owned(), true);
+            }
+        }
+
+        let krate = if self.rlua_kept { "rlua" } else { "mlua" };
+        for (edit, binding) in std::mem::take(&mut self.context_bindings) {
+            self.edits[edit].text = format!("{{ let {}: &{}::Lua = &", binding, krate);
+        }
+    }
+
+    fn position(&self, offset: usize) -> LineColumn {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let column = self.source[self.line_starts[line - 1]..offset]
+            .chars()
+            .count();
+        LineColumn { line, column }
+    }
+
+    fn remove_item(&mut self, (start, end): (usize, usize)) {
+        // Take the whole line when the item is on a line of its own.
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let (start, end) = if self.source[line_start..start].trim().is_empty()
+            && self.source[end..].starts_with('\n')
+        {
+            (line_start, end + 1)
+        } else {
+            (start, end)
+        };
+        self.edit((start, end), "");
+    }
+
+    fn remove_from_group(&mut self, elements: &[(usize, usize)], removed: &[(usize, usize)]) {
+        let mut i = 0;
+        while i < elements.len() {
+            if !removed.contains(&elements[i]) {
+                i += 1;
+                continue;
+            }
+            let first = i;
+            while i < elements.len() && removed.contains(&elements[i]) {
+                i += 1;
+            }
+            if i < elements.len() {
+                self.edit((elements[first].0, elements[i].0), "");
+            } else {
+                self.edit((elements[first - 1].1, elements[i - 1].1), "");
+            }
+        }
+    }
+}
+
+impl<'ast, 'a> Visit<'ast> for Migrator<'a> {
+    fn visit_expr_method_call(&mut self, call: &'ast ExprMethodCall) {
+        let method = call.method.to_string();
+        if method == "context" && call.args.len() == 1 {
+            if let Some(Expr::Closure(closure)) = call.args.first() {
+                // Temporaries in a more complex receiver would not live long enough once
+                // it is bound with `let`.
+                let simple_receiver = matches!(
+                    &*call.receiver,
+                    Expr::Path(_) | Expr::Field(_) | Expr::Call(_)
+                );
+                if closure.inputs.len() == 1 && simple_receiver {
+                    self.rewrite_context(call, closure);
+                    self.report(
+                        call.method.span(),
+                        "`RluaCompat::context` closure can be inlined".to_owned(),
+                        true,
+                    );
+                } else {
+                    self.needs_rlua_compat = true;
+                    self.report(
+                        call.method.span(),
+                        "`RluaCompat::context` closure could not be inlined; call the methods \
+                         on `Lua` directly"
+                            .to_owned(),
+                        false,
+                    );
+                }
+            }
+        } else if let Some((_, hint)) = COMPAT_METHODS.iter().find(|(m, _)| *m == method) {
+            self.needs_rlua_compat |= RLUA_COMPAT_METHODS.contains(&method.as_str());
+            self.report(
+                call.method.span(),
+                format!("`{}` is only provided by rlua; {}", method, hint),
+                false,
+            );
+        } else if let Some(base) = method.strip_prefix("add_context_") {
+            self.report(
+                call.method.span(),
+                format!(
+                    "`{}` is only provided by rlua; use `add_{}` with a callback taking `&Lua`",
+                    method, base
+                ),
+                false,
+            );
+        } else {
+            self.rename(&call.method);
+        }
+        visit::visit_expr_method_call(self, call);
+    }
+
+    fn visit_path(&mut self, path: &'ast SynPath) {
+        let first = &path.segments[0].ident;
+        if first == "rlua" && path.segments.len() > 1 {
+            let rlua_only = path.segments.iter().skip(1).find_map(|segment| {
+                let name = segment.ident.to_string();
+                rlua_only_hint(&name).map(|hint| (name, hint))
+            });
+            if let Some((name, hint)) = rlua_only {
+                let message = format!("`{}` is only provided by rlua; {}", name, hint);
+                self.report(first.span(), message, false);
+                self.rlua_kept = true;
+            } else {
+                self.edit(self.range(first.span()), "mlua");
+                self.report(
+                    first.span(),
+                    "`rlua` can be replaced by `mlua`".to_owned(),
+                    true,
+                );
+            }
+        } else if path.segments.len() == 1 && self.rlua_names.contains(&first.to_string()) {
+            let hint = rlua_only_hint(&first.to_string()).unwrap_or("");
+            let message = format!("`{}` is only provided by rlua; {}", first, hint);
+            self.report(first.span(), message, false);
+        }
+        if first == "RluaCompat" && path.segments.len() > 1 {
+            self.needs_rlua_compat = true;
+        }
+        for segment in &path.segments {
+            self.rename(&segment.ident);
+        }
+        visit::visit_path(self, path);
+    }
+
+    fn visit_item_use(&mut self, item: &'ast ItemUse) {
+        match &item.tree {
+            UseTree::Path(path) if path.ident == "rlua" => {
+                self.visit_rlua_use(item, &path.tree, path.ident.span());
+            }
+            UseTree::Name(name) if name.ident == "rlua" => {
+                self.edit(self.range(name.ident.span()), "mlua");
+            }
+            _ => {}
+        }
+        rename_use_tree(self, &item.tree);
+    }
+
+    fn visit_item_extern_crate(&mut self, item: &'ast ItemExternCrate) {
+        if item.ident == "rlua" {
+            self.extern_crates.push(item.ident.span());
+        }
+    }
+}
+
+fn rlua_only_hint(name: &str) -> Option<&'static str> {
+    if RLUA_MODULES.contains(&name) {
+        return Some("keep the rlua dependency for it");
+    }
+    RLUA_ONLY
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, hint)| *hint)
+}
+
+// Imports of compat traits are handled in `Migrator::finish` instead.
+fn is_compat_trait(ident: &Ident) -> bool {
+    COMPAT_TRAITS.iter().any(|(name, _, _)| ident == name)
+}
+
+fn rename_use_tree(migrator: &mut Migrator, tree: &UseTree) {
+    match tree {
+        UseTree::Path(path) => rename_use_tree(migrator, &path.tree),
+        UseTree::Name(name) if !is_compat_trait(&name.ident) => migrator.rename(&name.ident),
+        UseTree::Rename(rename) if !is_compat_trait(&rename.ident) => {
+            migrator.rename(&rename.ident)
+        }
+        UseTree::Group(group) => {
+            for tree in &group.items {
+                rename_use_tree(migrator, tree);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Whether inlining the closure body would change where a `return` or `?` goes.
+fn escapes_closure(body: &Expr) -> bool {
+    struct Escapes(bool);
+
+    impl<'ast> Visit<'ast> for Escapes {
+        fn visit_expr_return(&mut self, _: &'ast syn::ExprReturn) {
+            self.0 = true;
+        }
+
+        fn visit_expr_try(&mut self, _: &'ast syn::ExprTry) {
+            self.0 = true;
+        }
+
+        fn visit_expr_closure(&mut self, _: &'ast ExprClosure) {}
+
+        fn visit_expr_async(&mut self, _: &'ast syn::ExprAsync) {}
+
+        fn visit_item(&mut self, _: &'ast syn::Item) {}
+
+        // Macro arguments aren't parsed, so look for the tokens instead.
+        fn visit_macro(&mut self, mac: &'ast syn::Macro) {
+            self.0 |= tokens_escape(mac.tokens.clone());
+        }
+    }
+
+    fn tokens_escape(tokens: TokenStream) -> bool {
+        tokens.into_iter().any(|token| match token {
+            TokenTree::Group(group) => tokens_escape(group.stream()),
+            TokenTree::Ident(ident) => ident == "return",
+            TokenTree::Punct(punct) => punct.as_char() == '?',
+            TokenTree::Literal(_) => false,
+        })
+    }
+
+    let mut escapes = Escapes(false);
+    escapes.visit_expr(body);
+    escapes.0
+}
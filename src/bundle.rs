@@ -0,0 +1,61 @@
+//! Lua modules embedded in the binary with `include_lua!`.
+
+use crate::{ChunkMode, Lua, MultiValue, Result, Table};
+
+/// A set of Lua modules embedded with `include_lua!`.
+///
+/// `include_lua!("path/to/dir")` embeds every `.lua` file below the directory (relative
+/// to the crate's `Cargo.toml`), naming each module after its path the way `require`
+/// would find it: `util/strings.lua` becomes `util.strings`, and `util/init.lua` becomes
+/// `util`.  `register` then makes them available to `require`:
+///
+/// ```ignore
+/// let lua = Lua::new();
+/// rlua::include_lua!("scripts").register(&lua)?;
+/// lua.load("require('util.strings')").exec()?;
+/// ```
+///
+/// With the `include-lua-check` feature, every file is parsed while building, with the Lua
+/// version rlua is built with, and a syntax error fails the build with the file and line.
+/// Otherwise it only shows up once the module is required.
+///
+/// Changes to files which were already embedded are tracked, but a procedural macro can't
+/// ask Cargo to watch a directory.  For files added to it to be picked up without a clean
+/// build, the crate using the macro needs a build script with
+/// `println!("cargo:rerun-if-changed=path/to/dir");`, as Cargo then scans the whole
+/// directory for changes.
+#[derive(Clone, Copy, Debug)]
+pub struct Bundle {
+    modules: &'static [(&'static str, &'static str, &'static [u8])],
+}
+
+impl Bundle {
+    #[doc(hidden)]
+    pub const fn new(modules: &'static [(&'static str, &'static str, &'static [u8])]) -> Self {
+        Bundle { modules }
+    }
+
+    /// Returns the names and sources of the embedded modules, sorted by path.
+    pub fn modules(&self) -> impl Iterator<Item = (&'static str, &'static [u8])> {
+        self.modules.iter().map(|&(name, _, source)| (name, source))
+    }
+
+    /// Adds a loader for each module to `package.preload`.
+    ///
+    /// Modules are only compiled when they are first required.  Their chunk names are
+    /// their paths, including the directory given to `include_lua!`.
+    pub fn register(&self, lua: &Lua) -> Result<()> {
+        let package: Table = lua.globals().get("package")?;
+        let preload: Table = package.get("preload")?;
+        for &(name, path, source) in self.modules {
+            let loader = lua.create_function(move |lua, args: MultiValue| {
+                lua.load(source)
+                    .set_name(format!("@{}", path))
+                    .set_mode(ChunkMode::Text)
+                    .call::<_, MultiValue>(args)
+            })?;
+            preload.set(name, loader)?;
+        }
+        Ok(())
+    }
+}
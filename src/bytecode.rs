@@ -0,0 +1,171 @@
+//! A cache of compiled chunks, signed so that they can be trusted when loaded back.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::{ChunkMode, Function, Lua, Result};
+
+/// Stores the bytecode of compiled chunks on disk, and loads it back instead of compiling
+/// the source again.
+///
+/// Lua does not check bytecode, so loading a tampered binary chunk can crash the process
+/// or worse.  Each entry is therefore signed with HMAC-SHA-256 using a key provided by the
+/// host, and only loaded when the signature matches.  Entries are keyed by a hash of the
+/// chunk name and source, and tagged with the Lua version and pointer width they were
+/// compiled for; any mismatch makes the cache compile the source instead.
+///
+/// ```
+/// # use rlua::{Lua, Result, bytecode::Cache};
+/// # fn main() -> Result<()> {
+/// # let dir = std::env::temp_dir().join("rlua-bytecode-doctest");
+/// let cache = Cache::new(dir, b"a secret key".to_vec());
+/// let lua = Lua::new();
+/// let f = cache.load(&lua, "=example", "return 1 + 1")?;
+/// assert_eq!(f.call::<_, i32>(())?, 2);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The key is all that protects the process from the contents of the cache directory, so
+/// keep it out of reach of whoever can write there.
+#[derive(Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    key: Vec<u8>,
+}
+
+impl Cache {
+    /// Creates a cache storing its entries in `dir`, which is created when needed.
+    pub fn new(dir: impl Into<PathBuf>, key: impl Into<Vec<u8>>) -> Self {
+        Cache {
+            dir: dir.into(),
+            key: key.into(),
+        }
+    }
+
+    /// Returns the directory the entries are stored in.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Loads a chunk from its cache entry, or if there is no valid entry compiles the source
+    /// and stores it.
+    ///
+    /// `name` is the chunk name, as with `Chunk::set_name`.  Failing to write the entry is
+    /// not an error.
+    pub fn load<'lua>(
+        &self,
+        lua: &'lua Lua,
+        name: &str,
+        source: impl AsRef<[u8]>,
+    ) -> Result<Function<'lua>> {
+        let source = source.as_ref();
+        if let Some(function) = self.get(lua, name, source) {
+            return Ok(function);
+        }
+        let function = lua
+            .load(source)
+            .set_name(name)
+            .set_mode(ChunkMode::Text)
+            .into_function()?;
+        let _ = self.store(name, source, &function.dump(false));
+        Ok(function)
+    }
+
+    /// Loads a chunk from its cache entry only, returning `None` if there is no entry or it
+    /// isn't valid.
+    pub fn get<'lua>(
+        &self,
+        lua: &'lua Lua,
+        name: &str,
+        source: impl AsRef<[u8]>,
+    ) -> Option<Function<'lua>> {
+        let source_hash = source_hash(name, source.as_ref());
+        let entry = fs::read(self.entry_path(&source_hash)).ok()?;
+        let entry = entry.strip_prefix(MAGIC)?;
+        if entry.len() < MAC_LEN {
+            return None;
+        }
+        let (mac, bytecode) = entry.split_at(MAC_LEN);
+        self.mac(&source_hash, bytecode).verify_slice(mac).ok()?;
+        lua.load(bytecode)
+            .set_name(name)
+            .set_mode(ChunkMode::Binary)
+            .into_function()
+            .ok()
+    }
+
+    fn store(&self, name: &str, source: &[u8], bytecode: &[u8]) -> io::Result<()> {
+        let source_hash = source_hash(name, source);
+        let path = self.entry_path(&source_hash);
+        fs::create_dir_all(&self.dir)?;
+
+        // Write to a temporary file first, so that readers never see a partial entry.
+        let temp_path = path.with_extension(format!("tmp{}", std::process::id()));
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(MAGIC)?;
+        file.write_all(&self.mac(&source_hash, bytecode).finalize().into_bytes())?;
+        file.write_all(bytecode)?;
+        drop(file);
+        let result = fs::rename(&temp_path, &path);
+        if result.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        result
+    }
+
+    fn mac(&self, source_hash: &[u8; 32], bytecode: &[u8]) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(tag().as_bytes());
+        mac.update(source_hash);
+        mac.update(bytecode);
+        mac
+    }
+
+    fn entry_path(&self, source_hash: &[u8; 32]) -> PathBuf {
+        let mut file_name = String::new();
+        for byte in source_hash {
+            let _ = write!(file_name, "{:02x}", byte);
+        }
+        let _ = write!(file_name, "-{}.luac", tag());
+        self.dir.join(file_name)
+    }
+}
+
+impl std::fmt::Debug for Cache {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Cache")
+            .field("dir", &self.dir)
+            .finish_non_exhaustive()
+    }
+}
+
+const MAGIC: &[u8] = b"rlua bytecode 1\n";
+const MAC_LEN: usize = 32;
+
+// Bytecode is only compatible with the Lua version and pointer width it was compiled for.
+fn tag() -> String {
+    #[cfg(rlua_lua54)]
+    let version = "lua54";
+    #[cfg(rlua_lua53)]
+    let version = "lua53";
+    #[cfg(rlua_luajit)]
+    let version = "luajit";
+    #[cfg(all(rlua_lua51, not(rlua_luajit)))]
+    let version = "lua51";
+    format!("{}-{}bit", version, usize::BITS)
+}
+
+fn source_hash(name: &str, source: &[u8]) -> [u8; 32] {
+    let mut hash = Sha256::new();
+    hash.update((name.len() as u64).to_le_bytes());
+    hash.update(name.as_bytes());
+    hash.update(source);
+    hash.finalize().into()
+}
@@ -0,0 +1,222 @@
+//! Host functions which scripts may only call when granted the capability they require.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error as StdError;
+use std::fmt;
+use std::string::String as StdString;
+
+use crate::{
+    AsChunk, Chunk, Error, FromLuaMulti, Function, IntoLuaMulti, Lua, MultiValue, Result, Table,
+    Value,
+};
+
+/// The error raised when a script calls a host function without having been granted the
+/// capability it requires.
+///
+/// Find it with [`ErrorExt::find_cause`](crate::ErrorExt::find_cause).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CapabilityDenied {
+    /// The name the function was registered under.
+    pub function: StdString,
+    /// The capability it requires.
+    pub capability: StdString,
+}
+
+impl fmt::Display for CapabilityDenied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} requires the '{}' capability",
+            self.function, self.capability
+        )
+    }
+}
+
+impl StdError for CapabilityDenied {}
+
+/// A registry of host functions, each requiring a capability such as `"fs.read"` or
+/// `"net"`.
+///
+/// Chunks are loaded with a set of granted capabilities, and see every registered function
+/// in their environment.  Calling one whose capability wasn't granted raises a
+/// `CapabilityDenied` error instead of running it:
+///
+/// ```
+/// # use rlua::{ErrorExt, Lua, Result, capability::{CapabilityDenied, HostApi}};
+/// # fn main() -> Result<()> {
+/// let lua = Lua::new();
+/// let api = HostApi::new(&lua)
+///     .function("fs.read", "fs.read", |_, path: String| Ok(format!("contents of {}", path)))?
+///     .function("fetch", "net", |_, url: String| Ok(format!("response from {}", url)))?;
+///
+/// let contents: String = api.load("return fs.read('a.txt')", ["fs"])?.eval()?;
+/// assert_eq!(contents, "contents of a.txt");
+///
+/// let err = api.load("fetch('https://example.com')", ["fs"])?.exec().unwrap_err();
+/// assert!(err.find_cause::<CapabilityDenied>().is_some());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Granting a capability also grants the ones below it, so `"fs"` covers `"fs.read"` and
+/// `"fs.write"`.  A function name containing dots is placed in nested tables, so
+/// `"fs.read"` is called as `fs.read(...)`.
+#[derive(Clone)]
+pub struct HostApi<'lua> {
+    lua: &'lua Lua,
+    functions: BTreeMap<StdString, HostFunction<'lua>>,
+}
+
+#[derive(Clone)]
+struct HostFunction<'lua> {
+    capability: StdString,
+    function: Function<'lua>,
+}
+
+impl<'lua> HostApi<'lua> {
+    /// Creates an empty registry for functions of `lua`.
+    pub fn new(lua: &'lua Lua) -> Self {
+        HostApi {
+            lua,
+            functions: BTreeMap::new(),
+        }
+    }
+
+    /// Registers a function under `name`, callable by scripts granted `capability`.
+    ///
+    /// The function is created right away, with the same arguments as
+    /// `Lua::create_function`.  Registering a name again replaces the previous function.
+    pub fn function<A, R, F>(mut self, name: &str, capability: &str, func: F) -> Result<Self>
+    where
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+        F: Fn(&'lua Lua, A) -> Result<R> + 'static,
+    {
+        let function = self.lua.create_function(func)?;
+        self.functions.insert(
+            name.to_owned(),
+            HostFunction {
+                capability: capability.to_owned(),
+                function,
+            },
+        );
+        Ok(self)
+    }
+
+    /// Returns the names of the registered functions and the capabilities they require.
+    pub fn functions(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.functions
+            .iter()
+            .map(|(name, function)| (name.as_str(), function.capability.as_str()))
+    }
+
+    /// Adds the registered functions to `env`, which can be a table made by
+    /// `Sandbox::create_env`.
+    ///
+    /// Functions whose capability isn't among the `granted` ones are replaced by ones
+    /// raising `CapabilityDenied`, so later changes to the registry don't affect the
+    /// functions already added.
+    ///
+    /// The tables for dotted names are only created where `env` doesn't already hold one.
+    /// If `env` can see one through its metatable, such as the `string` library in an
+    /// environment from `load`, the new table falls back to it through its own `__index`,
+    /// so registering `"string.secret"` doesn't hide `string.format`.  A name running into a
+    /// value which isn't a table is an error.
+    pub fn install<S: AsRef<str>>(
+        &self,
+        env: &Table<'lua>,
+        granted: impl IntoIterator<Item = S>,
+    ) -> Result<()> {
+        let granted: BTreeSet<StdString> = granted
+            .into_iter()
+            .map(|capability| capability.as_ref().to_owned())
+            .collect();
+
+        for (name, function) in &self.functions {
+            let wrapper = if is_granted(&granted, &function.capability) {
+                function.function.clone()
+            } else {
+                let denied = CapabilityDenied {
+                    function: name.clone(),
+                    capability: function.capability.clone(),
+                };
+                self.lua
+                    .create_function(move |_, _: MultiValue| -> Result<()> {
+                        Err(Error::external(denied.clone()))
+                    })?
+            };
+
+            let mut table = env.clone();
+            let mut path = name.split('.').peekable();
+            while let Some(part) = path.next() {
+                if path.peek().is_none() {
+                    table.raw_set(part, wrapper)?;
+                    break;
+                }
+                if let Value::Table(inner) = table.raw_get::<_, Value>(part)? {
+                    table = inner;
+                    continue;
+                }
+                let inner = self.lua.create_table()?;
+                match table.get::<_, Value>(part)? {
+                    Value::Nil => {}
+                    Value::Table(existing) => {
+                        let metatable = self.lua.create_table()?;
+                        metatable.raw_set("__index", existing)?;
+                        inner.set_metatable(Some(metatable));
+                    }
+                    value => {
+                        return Err(Error::RuntimeError(format!(
+                            "cannot install '{}': '{}' is a {}, not a table",
+                            name,
+                            part,
+                            value.type_name()
+                        )))
+                    }
+                }
+                table.raw_set(part, inner.clone())?;
+                table = inner;
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads a chunk with an environment holding the registered functions bound to the
+    /// `granted` capabilities.
+    ///
+    /// Other names are looked up in the globals, through the environment's metatable.  For
+    /// untrusted scripts, `install` the functions into a sandbox environment instead.
+    #[track_caller]
+    pub fn load<'a, S: AsRef<str>>(
+        &self,
+        chunk: impl AsChunk<'lua, 'a>,
+        granted: impl IntoIterator<Item = S>,
+    ) -> Result<Chunk<'lua, 'a>> {
+        let env = self.lua.create_table()?;
+        let metatable = self.lua.create_table()?;
+        metatable.raw_set("__index", self.lua.globals())?;
+        env.set_metatable(Some(metatable));
+        self.install(&env, granted)?;
+        Ok(self.lua.load(chunk).set_environment(env))
+    }
+}
+
+impl fmt::Debug for HostApi<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.functions()).finish()
+    }
+}
+
+// Whether `capability` or one of the capabilities above it was granted.
+fn is_granted(granted: &BTreeSet<StdString>, capability: &str) -> bool {
+    let mut prefix = capability;
+    loop {
+        if granted.contains(prefix) {
+            return true;
+        }
+        match prefix.rsplit_once('.') {
+            Some((parent, _)) => prefix = parent,
+            None => return false,
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Helpers for reading chunk sources, shared by the replacement `load` functions and the
+//! module resolver.
+
+use std::string::String as StdString;
+
+use crate::{ffi, Error, Function, Lua, Result, String, Value};
+
+/// Creates the function the load wrappers read chunks through.
+///
+/// It's called with the name of the wrapper (for error messages) and the chunk passed to
+/// it, a string or a reader function, and returns either the chunk source or `nil` and an
+/// error message.  Compiled bytecode is refused.
+pub(crate) fn create_source_reader<'lua>(lua: &'lua Lua) -> Result<Function<'lua>> {
+    lua.create_function(|lua, (who, chunk): (StdString, Value)| {
+        let source = match chunk {
+            Value::String(s) => s.as_bytes().to_vec(),
+            Value::Function(reader) => {
+                let mut source = Vec::new();
+                loop {
+                    match reader.call::<_, Value>(())? {
+                        Value::Nil => break,
+                        Value::String(s) if s.as_bytes().is_empty() => break,
+                        Value::String(s) => source.extend_from_slice(s.as_bytes()),
+                        _ => {
+                            let message = "reader function must return a string";
+                            return Ok((None, Some(message.to_owned())));
+                        }
+                    }
+                }
+                source
+            }
+            other => {
+                return Err(Error::RuntimeError(format!(
+                    "bad argument #1 to '{}' (string expected, got {})",
+                    who,
+                    other.type_name()
+                )))
+            }
+        };
+        checked_source(lua, &who, source)
+    })
+}
+
+/// Skips an initial `#!` line, keeping the newline so that line numbers are preserved.
+pub(crate) fn skip_shebang(source: &mut Vec<u8>) {
+    if source.first() == Some(&b'#') {
+        let end = source
+            .iter()
+            .position(|&b| b == b'\n')
+            .unwrap_or(source.len());
+        source.drain(..end);
+    }
+}
+
+/// Returns the source as a Lua string, or `nil` and an error message if it's bytecode.
+pub(crate) fn checked_source<'lua>(
+    lua: &'lua Lua,
+    who: &str,
+    source: Vec<u8>,
+) -> Result<(Option<String<'lua>>, Option<StdString>)> {
+    if source.starts_with(ffi::LUA_SIGNATURE) {
+        let message = format!("rlua {}: attempt to load bytecode", who);
+        return Ok((None, Some(message)));
+    }
+    Ok((Some(lua.create_string(source)?), None))
+}
@@ -0,0 +1,316 @@
+use std::io::Read;
+use std::string::String as StdString;
+
+use crate::chunk::{checked_source, create_source_reader, skip_shebang};
+use crate::context::{Context, ContextChunk};
+use crate::{
+    AnyUserData, Chunk, FromLuaMulti, Function, IntoLua, IntoLuaMulti, Lua, LuaOptions, MultiValue,
+    Nil, Result, Scope, StdLib, Table, UserData, Value,
+};
+
+bitflags::bitflags! {
+    /// Flags controlling the extra setup done when creating a `Lua` state with
+    /// [`RluaCompat::unsafe_new_with_flags`].
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+    pub struct InitFlags: u32 {
+        /// Wrap `pcall` and `xpcall` so that they don't allow catching Rust panics.
+        ///
+        /// This is the inverse of `LuaOptions::catch_rust_panics`.
+        const PCALL_WRAPPERS = 0x1;
+        /// Wrap `load`, `loadfile`, `dofile` (and `loadstring` on Lua 5.1) to prevent
+        /// loading compiled/bytecode chunks.
+        const LOAD_WRAPPERS = 0x2;
+        /// Remove `package.loadlib` and the `package.searchers` (`package.loaders` on
+        /// Lua 5.1) entries which allow loading compiled C libraries.
+        const REMOVE_LOADLIB = 0x4;
+        const DEFAULT = Self::PCALL_WRAPPERS.bits()
+            | Self::LOAD_WRAPPERS.bits()
+            | Self::REMOVE_LOADLIB.bits();
+        const NONE = 0;
+    }
+}
+
+impl Default for InitFlags {
+    fn default() -> Self {
+        InitFlags::DEFAULT
+    }
+}
+
+pub trait RluaCompat {
+    #[deprecated = "Context is no longer needed; call methods on Lua directly."]
+    fn context<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(Context) -> R;
+
+    /// Creates a new Lua state with the given standard libraries, applying the
+    /// setup described by `flags`.
+    ///
+    /// # Safety
+    /// Like `Lua::unsafe_new_with`, the created state may allow loading C modules and
+    /// the `debug` library.  Leaving out `InitFlags::PCALL_WRAPPERS` allows Lua code to
+    /// catch Rust panics.
+    unsafe fn unsafe_new_with_flags(libs: StdLib, flags: InitFlags) -> Lua
+    where
+        Self: Sized;
+
+    /// Replaces `load`, `loadfile`, `dofile` (and `loadstring` where present) with
+    /// versions which refuse to load compiled Lua bytecode.
+    ///
+    /// This is what `InitFlags::LOAD_WRAPPERS` does; it can also be applied to a state
+    /// created with `Lua::new` or `Lua::new_with`.
+    fn install_load_wrappers(&self) -> Result<()>;
+
+    /// Adds the math functions deprecated in Lua 5.3 (`math.pow`, `math.ldexp`,
+    /// `math.frexp`, `math.cosh`, `math.sinh`, `math.tanh` and `math.log10`) to
+    /// `math`, where the Lua library doesn't already provide them.
+    ///
+    /// With one of the `builtin-lua53` or `builtin-lua54` features, these are added
+    /// whenever `math` is opened, so every state has them already.  With another Lua,
+    /// states created with `unsafe_new_with_flags` have them added automatically.
+    #[cfg(feature = "lua-compat-mathlib")]
+    fn load_compat_mathlib(&self) -> Result<()>;
+}
+
+impl RluaCompat for Lua {
+    fn context<R, F>(&self, f: F) -> R
+    where
+        F: FnOnce(Context) -> R,
+    {
+        f(Context::from(self))
+    }
+
+    unsafe fn unsafe_new_with_flags(libs: StdLib, flags: InitFlags) -> Lua {
+        let options =
+            LuaOptions::new().catch_rust_panics(!flags.contains(InitFlags::PCALL_WRAPPERS));
+        let lua = Lua::unsafe_new_with(libs, options);
+
+        #[cfg(feature = "lua-compat-mathlib")]
+        lua.load_compat_mathlib()
+            .expect("error loading the compat math functions");
+
+        if flags.contains(InitFlags::LOAD_WRAPPERS) {
+            lua.install_load_wrappers()
+                .expect("error installing load wrappers");
+        }
+        if flags.contains(InitFlags::REMOVE_LOADLIB) && libs.contains(StdLib::PACKAGE) {
+            remove_loadlib(&lua).expect("error removing package.loadlib");
+        }
+
+        lua
+    }
+
+    fn install_load_wrappers(&self) -> Result<()> {
+        let globals = self.globals();
+        let real_load: Function = globals.raw_get("load")?;
+        // Lua 5.1's `load` only accepts a reader function and has no mode argument,
+        // so text chunks are loaded through `loadstring` instead.
+        #[cfg(all(rlua_lua51, not(rlua_luajit)))]
+        let real_loadstring: Option<Function> = globals.raw_get("loadstring")?;
+        #[cfg(not(all(rlua_lua51, not(rlua_luajit))))]
+        let real_loadstring: Option<Function> = None;
+
+        let read_chunk = create_source_reader(self)?;
+
+        let read_file =
+            self.create_function(|lua, (who, filename): (StdString, Option<StdString>)| {
+                let read = match &filename {
+                    Some(filename) => std::fs::read(filename),
+                    None => {
+                        let mut source = Vec::new();
+                        std::io::stdin().read_to_end(&mut source).map(|_| source)
+                    }
+                };
+                let mut source = match read {
+                    Ok(source) => source,
+                    Err(err) => {
+                        let filename = filename.as_deref().unwrap_or("stdin");
+                        let message = format!("cannot open {}: {}", filename, err);
+                        return Ok((None, Some(message)));
+                    }
+                };
+                skip_shebang(&mut source);
+                checked_source(lua, &who, source)
+            })?;
+
+        self.load(LOAD_WRAPPERS_SOURCE)
+            .set_name("=rlua load wrappers")
+            .call((real_load, real_loadstring, read_chunk, read_file))
+    }
+
+    #[cfg(feature = "lua-compat-mathlib")]
+    fn load_compat_mathlib(&self) -> Result<()> {
+        crate::mathlib::load_compat_mathlib(self)
+    }
+}
+
+/// The 0.19 names for creating userdata inside a `Lua::scope`.
+///
+/// `Scope::create_nonstatic_userdata` kept its name and signature in mlua, so only
+/// `create_static_userdata` needs a shim.
+pub trait ScopeCompat<'lua> {
+    /// Creates a userdata which expires on scope drop, and does not need to be `Send`.
+    #[deprecated = "Scope::create_static_userdata has become Scope::create_userdata"]
+    fn create_static_userdata<T>(&self, data: T) -> Result<AnyUserData<'lua>>
+    where
+        T: UserData + 'static;
+}
+
+impl<'lua, 'scope> ScopeCompat<'lua> for Scope<'lua, 'scope> {
+    fn create_static_userdata<T>(&self, data: T) -> Result<AnyUserData<'lua>>
+    where
+        T: UserData + 'static,
+    {
+        self.create_userdata(data)
+    }
+}
+
+/// The 0.19 entry point for loading precompiled chunks, such as the output of
+/// `Function::dump`, from Rust.
+///
+/// Chunks from `Context::load` are text only, so loading them with `into_function` (or
+/// `exec`, `eval` and `call`) never runs bytecode.  The load wrappers only restrict what
+/// Lua code can load; they have no effect on chunks loaded through this method.
+pub trait ChunkCompat<'lua> {
+    /// Loads this chunk into a `Function`, accepting either Lua source or bytecode.
+    ///
+    /// Which one the chunk holds is detected from its contents, unless its mode was set
+    /// with `set_mode`.
+    ///
+    /// # Safety
+    /// Lua does not verify bytecode, so loading a malformed or malicious binary chunk can
+    /// crash the interpreter.  Only use this with binary chunks from trusted sources.
+    unsafe fn into_function_allow_binary(self) -> Result<Function<'lua>>;
+}
+
+impl<'lua> ChunkCompat<'lua> for Chunk<'lua, '_> {
+    unsafe fn into_function_allow_binary(self) -> Result<Function<'lua>> {
+        // mlua picks the mode from the source when none was set.
+        self.into_function()
+    }
+}
+
+impl<'lua> ChunkCompat<'lua> for ContextChunk<'lua, '_> {
+    unsafe fn into_function_allow_binary(self) -> Result<Function<'lua>> {
+        self.into_chunk_allow_binary().into_function()
+    }
+}
+
+// Installs the text-only `load`, `loadfile`, `dofile` and `loadstring` functions.  The
+// helpers passed in return either the chunk source or `nil` and an error message, and
+// reject compiled bytecode.
+const LOAD_WRAPPERS_SOURCE: &str = r#"
+    local real_load, real_loadstring, read_chunk, read_file = ...
+
+    local function load_text(source, chunkname, mode, ...)
+        if real_loadstring ~= nil then
+            return real_loadstring(source, chunkname)
+        end
+        return real_load(source, chunkname, mode or "t", ...)
+    end
+
+    local function file_chunkname(filename)
+        if filename == nil then
+            return "=stdin"
+        end
+        return "@" .. filename
+    end
+
+    load = function(chunk, chunkname, mode, ...)
+        local source, err = read_chunk("load", chunk)
+        if source == nil then
+            return nil, err
+        end
+        if chunkname == nil and type(chunk) ~= "string" then
+            chunkname = "=(load)"
+        end
+        return load_text(source, chunkname, mode, ...)
+    end
+
+    loadfile = function(filename, mode, ...)
+        local source, err = read_file("loadfile", filename)
+        if source == nil then
+            return nil, err
+        end
+        return load_text(source, file_chunkname(filename), mode, ...)
+    end
+
+    dofile = function(filename)
+        local source, err = read_file("dofile", filename)
+        if source == nil then
+            error(err, 2)
+        end
+        local f, err = load_text(source, file_chunkname(filename))
+        if f == nil then
+            error(err, 2)
+        end
+        return f()
+    end
+
+    if loadstring ~= nil then
+        loadstring = function(s, chunkname)
+            local source, err = read_chunk("loadstring", s)
+            if source == nil then
+                return nil, err
+            end
+            return load_text(source, chunkname)
+        end
+    end
+"#;
+
+fn remove_loadlib(lua: &Lua) -> Result<()> {
+    let package: Table = lua.globals().get("package")?;
+    package.set("loadlib", Nil)?;
+
+    #[cfg(not(rlua_lua51))]
+    let searchers: Table = package.get("searchers")?;
+    #[cfg(rlua_lua51)]
+    let searchers: Table = package.get("loaders")?;
+
+    // The third and fourth searchers look for a loader in a C library
+    searchers.raw_set(4, Nil)?;
+    searchers.raw_set(3, Nil)?;
+
+    Ok(())
+}
+
+pub use mlua::IntoLua as ToLua;
+
+pub trait ToLuaCompat<'lua> {
+    #[deprecated = "ToLua::to_lua has become IntoLua::into_lua"]
+    fn to_lua(self, context: impl Into<Context<'lua>>) -> Result<Value<'lua>>;
+}
+
+impl<'lua, T: IntoLua<'lua>> ToLuaCompat<'lua> for T {
+    fn to_lua(self, context: impl Into<Context<'lua>>) -> Result<Value<'lua>> {
+        self.into_lua(context.into().lua())
+    }
+}
+
+pub use mlua::IntoLuaMulti as ToLuaMulti;
+
+pub trait ToLuaMultiCompat<'lua> {
+    #[deprecated = "ToLuaMulti::to_lua_multi has become IntoLuaMulti::into_lua_multi"]
+    fn to_lua_multi(self, context: impl Into<Context<'lua>>) -> Result<MultiValue<'lua>>;
+}
+
+impl<'lua, T: IntoLuaMulti<'lua>> ToLuaMultiCompat<'lua> for T {
+    fn to_lua_multi(self, context: impl Into<Context<'lua>>) -> Result<MultiValue<'lua>> {
+        self.into_lua_multi(context.into().lua())
+    }
+}
+
+/// `FromLuaMulti::from_lua_multi` taking a `Context`, as in 0.19.
+///
+/// mlua's `FromLuaMulti` has a method of the same name taking `&Lua`, so where both
+/// traits are in scope calls have to name the trait, as in
+/// `FromLuaMultiCompat::from_lua_multi(values, context)`.
+pub trait FromLuaMultiCompat<'lua>: Sized {
+    #[deprecated = "FromLuaMulti::from_lua_multi now takes &Lua"]
+    fn from_lua_multi(values: MultiValue<'lua>, context: impl Into<Context<'lua>>) -> Result<Self>;
+}
+
+impl<'lua, T: FromLuaMulti<'lua>> FromLuaMultiCompat<'lua> for T {
+    fn from_lua_multi(values: MultiValue<'lua>, context: impl Into<Context<'lua>>) -> Result<Self> {
+        FromLuaMulti::from_lua_multi(values, context.into().lua())
+    }
+}
@@ -0,0 +1,233 @@
+use std::cell::Cell;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::string::String as StdString;
+
+use crate::{
+    AnyUserData, AsChunk, Chunk, ChunkMode, FromLua, FromLuaMulti, Function, IntoLua, IntoLuaMulti,
+    Lua, MultiValue, RegistryKey, Result, String, Table, Thread, UserData, Value,
+};
+
+/// A handle to a `Lua` state, branded with the lifetime of the `RluaCompat::context` call
+/// (or Lua callback) it was created for.
+///
+/// This stands in for the 0.19 `Context` type.  It dereferences to `Lua`, and also has
+/// inherent versions of the 0.19 `Context` methods which return handles tied to `'lua`
+/// rather than to the borrow of the `Context` itself.  Callbacks created through
+/// `Context::create_function` receive a `Context` as in 0.19.
+#[derive(Copy, Clone)]
+#[repr(transparent)]
+pub struct Context<'lua> {
+    lua: &'lua Lua,
+    // Makes `'lua` invariant, so that handles from different contexts can't be mixed.
+    _invariant: PhantomData<Cell<&'lua ()>>,
+}
+
+impl<'lua> Context<'lua> {
+    /// Returns the `Lua` state this context refers to.
+    pub fn lua(self) -> &'lua Lua {
+        self.lua
+    }
+
+    pub fn globals(self) -> Table<'lua> {
+        self.lua.globals()
+    }
+
+    pub fn current_thread(self) -> Thread<'lua> {
+        self.lua.current_thread()
+    }
+
+    /// Loads a chunk of Lua source, which as in 0.19 is only read as bytecode through
+    /// `ChunkCompat::into_function_allow_binary`.
+    ///
+    /// As with `Lua::load`, the chunk is named after the caller's location by default.
+    #[track_caller]
+    pub fn load<'a>(self, chunk: impl AsChunk<'lua, 'a>) -> ContextChunk<'lua, 'a> {
+        ContextChunk {
+            chunk: self.lua.load(chunk),
+            mode_set: false,
+        }
+    }
+
+    pub fn create_string(self, s: impl AsRef<[u8]>) -> Result<String<'lua>> {
+        self.lua.create_string(s)
+    }
+
+    pub fn create_table(self) -> Result<Table<'lua>> {
+        self.lua.create_table()
+    }
+
+    pub fn create_table_from<K, V, I>(self, iter: I) -> Result<Table<'lua>>
+    where
+        K: IntoLua<'lua>,
+        V: IntoLua<'lua>,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        self.lua.create_table_from(iter)
+    }
+
+    pub fn create_sequence_from<T, I>(self, iter: I) -> Result<Table<'lua>>
+    where
+        T: IntoLua<'lua>,
+        I: IntoIterator<Item = T>,
+    {
+        self.lua.create_sequence_from(iter)
+    }
+
+    /// Wraps a Rust function or closure which takes a `Context`, creating a callable Lua
+    /// function handle to it.
+    pub fn create_function<A, R, F>(self, func: F) -> Result<Function<'lua>>
+    where
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+        F: Fn(Context<'lua>, A) -> Result<R> + Send + 'static,
+    {
+        self.lua
+            .create_function(move |lua, args| func(Context::from(lua), args))
+    }
+
+    /// Wraps a mutable Rust closure which takes a `Context`, creating a callable Lua
+    /// function handle to it.
+    pub fn create_function_mut<A, R, F>(self, mut func: F) -> Result<Function<'lua>>
+    where
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+        F: FnMut(Context<'lua>, A) -> Result<R> + Send + 'static,
+    {
+        self.lua
+            .create_function_mut(move |lua, args| func(Context::from(lua), args))
+    }
+
+    pub fn create_thread(self, func: Function<'lua>) -> Result<Thread<'lua>> {
+        self.lua.create_thread(func)
+    }
+
+    pub fn create_userdata<T>(self, data: T) -> Result<AnyUserData<'lua>>
+    where
+        T: UserData + Send + 'static,
+    {
+        self.lua.create_userdata(data)
+    }
+
+    pub fn coerce_string(self, v: Value<'lua>) -> Result<Option<String<'lua>>> {
+        self.lua.coerce_string(v)
+    }
+
+    pub fn pack<T: IntoLua<'lua>>(self, t: T) -> Result<Value<'lua>> {
+        self.lua.pack(t)
+    }
+
+    pub fn unpack<T: FromLua<'lua>>(self, value: Value<'lua>) -> Result<T> {
+        self.lua.unpack(value)
+    }
+
+    pub fn pack_multi<T: IntoLuaMulti<'lua>>(self, t: T) -> Result<MultiValue<'lua>> {
+        self.lua.pack_multi(t)
+    }
+
+    pub fn unpack_multi<T: FromLuaMulti<'lua>>(self, value: MultiValue<'lua>) -> Result<T> {
+        self.lua.unpack_multi(value)
+    }
+
+    pub fn named_registry_value<T: FromLua<'lua>>(self, name: &str) -> Result<T> {
+        self.lua.named_registry_value(name)
+    }
+
+    pub fn registry_value<T: FromLua<'lua>>(self, key: &RegistryKey) -> Result<T> {
+        self.lua.registry_value(key)
+    }
+}
+
+/// The chunk returned by `Context::load`.
+///
+/// This has the builder methods of `Chunk`, but unless a mode is set with `set_mode` the
+/// chunk is always loaded as text, except by `ChunkCompat::into_function_allow_binary`
+/// which accepts either.
+#[must_use = "`ContextChunk`s do nothing unless one of `exec`, `eval`, `call`, or `into_function` are called on them"]
+pub struct ContextChunk<'lua, 'a> {
+    chunk: Chunk<'lua, 'a>,
+    mode_set: bool,
+}
+
+impl<'lua, 'a> ContextChunk<'lua, 'a> {
+    pub fn set_name(self, name: impl Into<StdString>) -> Self {
+        ContextChunk {
+            chunk: self.chunk.set_name(name),
+            ..self
+        }
+    }
+
+    pub fn set_environment<V: IntoLua<'lua>>(self, env: V) -> Self {
+        ContextChunk {
+            chunk: self.chunk.set_environment(env),
+            ..self
+        }
+    }
+
+    pub fn set_mode(self, mode: ChunkMode) -> Self {
+        ContextChunk {
+            chunk: self.chunk.set_mode(mode),
+            mode_set: true,
+        }
+    }
+
+    pub fn exec(self) -> Result<()> {
+        self.into_chunk().exec()
+    }
+
+    pub fn eval<R: FromLuaMulti<'lua>>(self) -> Result<R> {
+        self.into_chunk().eval()
+    }
+
+    pub fn call<A: IntoLuaMulti<'lua>, R: FromLuaMulti<'lua>>(self, args: A) -> Result<R> {
+        self.into_chunk().call(args)
+    }
+
+    pub fn into_function(self) -> Result<Function<'lua>> {
+        self.into_chunk().into_function()
+    }
+
+    /// Returns the underlying `Chunk`, set to text mode unless `set_mode` was called.
+    pub fn into_chunk(self) -> Chunk<'lua, 'a> {
+        if self.mode_set {
+            self.chunk
+        } else {
+            self.chunk.set_mode(ChunkMode::Text)
+        }
+    }
+
+    // Leaves the mode to the chunk's contents, unless `set_mode` was called.
+    pub(crate) fn into_chunk_allow_binary(self) -> Chunk<'lua, 'a> {
+        self.chunk
+    }
+}
+
+impl<'lua> From<&'lua Lua> for Context<'lua> {
+    fn from(lua: &'lua Lua) -> Self {
+        Context {
+            lua,
+            _invariant: PhantomData,
+        }
+    }
+}
+
+impl<'lua> From<Context<'lua>> for &'lua Lua {
+    fn from(context: Context<'lua>) -> Self {
+        context.lua
+    }
+}
+
+impl Deref for Context<'_> {
+    type Target = Lua;
+
+    fn deref(&self) -> &Lua {
+        self.lua
+    }
+}
+
+impl fmt::Debug for Context<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Context").field(self.lua).finish()
+    }
+}
@@ -0,0 +1,300 @@
+//! Reproducible script runs, for replays and golden tests.
+
+use std::cell::Cell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::{Error, Integer, Lua, Number, Result, Table, Value};
+
+/// The time source behind `os.time`, `os.clock` and `os.date` in a deterministic state.
+pub trait Clock: 'static {
+    /// The current time in seconds since the Unix epoch, returned by `os.time()`.
+    fn time(&self) -> i64;
+    /// The processor time used so far in seconds, returned by `os.clock()`.
+    fn clock(&self) -> f64;
+}
+
+/// A `Clock` which only moves when the host advances it.
+///
+/// Clones share the same time, so the host can keep one to advance while the state uses
+/// another.
+#[derive(Clone, Debug)]
+pub struct VirtualClock(Arc<Mutex<VirtualTime>>);
+
+#[derive(Debug)]
+struct VirtualTime {
+    start: i64,
+    elapsed: Duration,
+}
+
+impl VirtualClock {
+    /// Creates a clock reading `start` seconds since the Unix epoch, with no processor
+    /// time used yet.
+    pub fn new(start: i64) -> Self {
+        VirtualClock(Arc::new(Mutex::new(VirtualTime {
+            start,
+            elapsed: Duration::ZERO,
+        })))
+    }
+
+    /// Moves the clock forward.
+    pub fn advance(&self, by: Duration) {
+        self.lock().elapsed += by;
+    }
+
+    /// Returns how far the clock has been moved forward in total.
+    pub fn elapsed(&self) -> Duration {
+        self.lock().elapsed
+    }
+
+    fn lock(&self) -> MutexGuard<'_, VirtualTime> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Clock for VirtualClock {
+    fn time(&self) -> i64 {
+        let time = self.lock();
+        time.start + time.elapsed.as_secs() as i64
+    }
+
+    fn clock(&self) -> f64 {
+        self.elapsed().as_secs_f64()
+    }
+}
+
+/// Adds `new_deterministic` and `set_deterministic` to `Lua`.
+///
+/// In a deterministic state a script produces the same results on every run:
+///
+/// - `math.random` and `math.randomseed` use a PRNG seeded with `seed`.  Calling
+///   `math.randomseed()` without arguments goes back to `seed`.
+/// - `os.time()` and `os.clock()` read the `Clock`, and `os.date` formats the clock's time
+///   by default.  Dates are always in UTC, and `os.time` converts date tables as UTC.
+/// - With the `lua-no-oslib` feature, `new_deterministic` creates an `os` table holding
+///   just `os.time` and `os.clock`.  There's no `os.date`, which needs the OS library.
+/// - `pairs` visits numbers in ascending order, then strings in byte order, then `false`
+///   and `true`.  `next` is left as it is.
+///
+/// Other sources of variation, such as `tostring` of tables or `collectgarbage("count")`,
+/// are not covered.  Neither are keys of other types, such as tables or functions, which
+/// `pairs` visits last in the order `next` gives them, as that depends on their addresses.
+pub trait LuaDeterministicExt: Sized {
+    /// Creates a new state with the safe standard libraries, made deterministic.
+    fn new_deterministic(seed: u64, clock: impl Clock) -> Result<Self>;
+
+    /// Makes an existing state deterministic, replacing the functions listed above in its
+    /// globals.
+    fn set_deterministic(&self, seed: u64, clock: impl Clock) -> Result<()>;
+}
+
+impl LuaDeterministicExt for Lua {
+    fn new_deterministic(seed: u64, clock: impl Clock) -> Result<Self> {
+        let lua = Lua::new();
+        #[cfg(feature = "lua-no-oslib")]
+        lua.globals().set("os", lua.create_table()?)?;
+        lua.set_deterministic(seed, clock)?;
+        Ok(lua)
+    }
+
+    fn set_deterministic(&self, seed: u64, clock: impl Clock) -> Result<()> {
+        let state = Rc::new(Cell::new(seed));
+        let clock = Rc::new(clock);
+
+        let random_state = state.clone();
+        let random =
+            self.create_function(move |_, (m, n): (Option<Integer>, Option<Integer>)| {
+                let bits = next_random(&random_state);
+                let (low, high) = match (m, n) {
+                    (None, _) => {
+                        return Ok(Value::Number(
+                            (bits >> 11) as Number / (1u64 << 53) as Number,
+                        ))
+                    }
+                    #[cfg(rlua_lua54)]
+                    (Some(0), None) => return Ok(Value::Integer(bits as Integer)),
+                    (Some(m), None) => (1, m),
+                    (Some(m), Some(n)) => (m, n),
+                };
+                if low > high {
+                    let arg = if n.is_some() { 2 } else { 1 };
+                    return Err(Error::RuntimeError(format!(
+                        "bad argument #{} to 'random' (interval is empty)",
+                        arg
+                    )));
+                }
+                let range = (high as i128 - low as i128 + 1) as u128;
+                let offset = ((bits as u128 * range) >> 64) as i64;
+                Ok(Value::Integer(low.wrapping_add(offset)))
+            })?;
+
+        let randomseed = self.create_function(move |_, x: Option<Number>| {
+            let seed = match x {
+                None => seed,
+                Some(x) if x.fract() == 0.0 => x as i64 as u64,
+                Some(x) => x.to_bits(),
+            };
+            state.set(seed);
+            Ok(())
+        })?;
+
+        let time_clock = clock.clone();
+        let time = self.create_function(move |_, date: Option<Table>| match date {
+            None => Ok(time_clock.time()),
+            Some(date) => utc_time(&date),
+        })?;
+        let clock = self.create_function(move |_, ()| Ok(clock.clock()))?;
+
+        let sorted_keys = self.create_function(|_, t: Table| {
+            let mut keys = t
+                .pairs::<Value, Value>()
+                .map(|pair| Ok(pair?.0))
+                .collect::<Result<Vec<_>>>()?;
+            keys.sort_by(compare_keys);
+            Ok(keys)
+        })?;
+
+        self.load(INSTALL_SOURCE)
+            .set_name("=rlua deterministic")
+            .call((random, randomseed, time, clock, sorted_keys))
+    }
+}
+
+// SplitMix64, which is small and good enough for scripts.
+fn next_random(state: &Cell<u64>) -> u64 {
+    let next = state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+    state.set(next);
+    let mut z = next;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// The order `pairs` visits keys in, leaving keys of other types in the order they came.
+fn compare_keys(a: &Value, b: &Value) -> Ordering {
+    fn rank(key: &Value) -> u8 {
+        match key {
+            Value::Integer(_) | Value::Number(_) => 0,
+            Value::String(_) => 1,
+            Value::Boolean(_) => 2,
+            _ => 3,
+        }
+    }
+
+    match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+        (Value::Integer(a), Value::Number(b)) => compare_integer_number(*a, *b),
+        (Value::Number(a), Value::Integer(b)) => compare_integer_number(*b, *a).reverse(),
+        (Value::String(a), Value::String(b)) => a.as_bytes().cmp(b.as_bytes()),
+        (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+// Compares exactly, where converting either side could round.
+fn compare_integer_number(i: Integer, n: Number) -> Ordering {
+    if n.is_nan() {
+        return Ordering::Equal;
+    }
+    // Integers are at most 64 bits, and 2^63 is exact as a float.
+    let limit = 9_223_372_036_854_775_808.0;
+    if n >= limit {
+        return Ordering::Less;
+    }
+    if n < -limit {
+        return Ordering::Greater;
+    }
+    let floor = n.floor();
+    match i.cmp(&(floor as Integer)) {
+        Ordering::Equal if n > floor => Ordering::Less,
+        order => order,
+    }
+}
+
+// Converts a date table to a time like `os.time` does, but as UTC rather than local time.
+fn utc_time(date: &Table) -> Result<i64> {
+    let field = |name: &str, default: Option<i64>| -> Result<i64> {
+        match (date.get::<_, Option<i64>>(name)?, default) {
+            (Some(value), _) => Ok(value),
+            (None, Some(default)) => Ok(default),
+            (None, None) => Err(Error::RuntimeError(format!(
+                "field '{}' missing in date table",
+                name
+            ))),
+        }
+    };
+    let months = field("year", None)? * 12 + field("month", None)? - 1;
+    let days =
+        days_from_civil(months.div_euclid(12), months.rem_euclid(12) + 1) + field("day", None)? - 1;
+    Ok(days * 86400
+        + field("hour", Some(12))? * 3600
+        + field("min", Some(0))? * 60
+        + field("sec", Some(0))?)
+}
+
+// The number of days from 1970-01-01 to the first of the given month.
+fn days_from_civil(year: i64, month: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Replaces the nondeterministic globals, skipping libraries which aren't loaded.
+const INSTALL_SOURCE: &str = r#"
+    local random, randomseed, time, clock, sorted_keys = ...
+    local getmetatable, rawget, type = getmetatable, rawget, type
+    local real_pairs, sub = pairs, string and string.sub
+
+    if type(math) == "table" then
+        math.random = random
+        math.randomseed = randomseed
+    end
+
+    if type(os) == "table" then
+        local real_date = os.date
+        os.time = time
+        os.clock = clock
+        if real_date ~= nil then
+            os.date = function(format, t)
+                format = format or "%c"
+                if type(format) == "string" and sub(format, 1, 1) ~= "!" then
+                    format = "!" .. format
+                end
+                if t == nil then
+                    t = time()
+                end
+                return real_date(format, t)
+            end
+        end
+    end
+
+    if real_pairs == nil then
+        return
+    end
+    pairs = function(t)
+        local meta = type(t) == "table" and getmetatable(t)
+        if type(t) ~= "table" or (type(meta) == "table" and rawget(meta, "__pairs") ~= nil) then
+            return real_pairs(t)
+        end
+
+        local keys = sorted_keys(t)
+        local i, n = 0, #keys
+        return function(t)
+            while i < n do
+                i = i + 1
+                local k = keys[i]
+                local v = rawget(t, k)
+                if v ~= nil then
+                    return k, v
+                end
+            end
+            return nil
+        end, t, nil
+    end
+"#;
@@ -0,0 +1,33 @@
+use std::error::Error as StdError;
+
+use crate::Error;
+
+/// Adds `find_cause` to `Error`.
+///
+/// The errors rlua raises, such as `interrupt::Interrupted`, are wrapped in an
+/// `Error::CallbackError` when they come from Rust code called by Lua, so look for them
+/// with `find_cause` rather than `Error::downcast_ref`.
+pub trait ErrorExt {
+    /// Like `Error::downcast_ref`, but also looks through the `CallbackError`s wrapping
+    /// errors which were raised from Rust code called by Lua, such as hooks.
+    fn find_cause<T>(&self) -> Option<&T>
+    where
+        T: StdError + 'static;
+}
+
+impl ErrorExt for Error {
+    fn find_cause<T>(&self) -> Option<&T>
+    where
+        T: StdError + 'static,
+    {
+        match self {
+            Error::CallbackError { cause, .. } | Error::WithContext { cause, .. } => {
+                cause.find_cause()
+            }
+            Error::ExternalError(err) => err
+                .downcast_ref()
+                .or_else(|| err.downcast_ref::<Error>()?.find_cause()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! Helpers for setting raw Lua hooks alongside the ones mlua installs.
+//!
+//! mlua can't report which hook is set, so these work with `lua_sethook` directly, and
+//! save and restore whatever hook (mlua's or another one of ours) was there before.
+
+use std::convert::TryFrom;
+use std::ffi::{c_char, c_int, c_void};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::{mem, ptr};
+
+use crate::{ffi, AnyUserData, Error, LightUserData, Lua, Result};
+
+/// A hook function along with its event mask and instruction count.
+#[derive(Clone, Copy)]
+pub(crate) struct SavedHook {
+    pub(crate) hook: Option<ffi::lua_Hook>,
+    pub(crate) mask: c_int,
+    pub(crate) count: c_int,
+}
+
+impl SavedHook {
+    pub(crate) unsafe fn get(state: *mut ffi::lua_State) -> Self {
+        SavedHook {
+            hook: ffi::lua_gethook(state),
+            mask: ffi::lua_gethookmask(state),
+            count: ffi::lua_gethookcount(state),
+        }
+    }
+
+    pub(crate) unsafe fn restore(self, state: *mut ffi::lua_State) {
+        if self.is_state_hook() {
+            // The events it needs may have changed since it was saved.
+            reset_thread_hook(state);
+        } else {
+            ffi::lua_sethook(state, self.hook, self.mask, self.count);
+        }
+    }
+
+    /// Calls the saved hook with an event it asked for.
+    pub(crate) unsafe fn forward(self, state: *mut ffi::lua_State, ar: *mut ffi::lua_Debug) {
+        if let Some(hook) = self.hook {
+            if self.mask & event_mask((*ar).event) != 0 {
+                hook(state, ar);
+            }
+        }
+    }
+
+    pub(crate) fn is_state_hook(self) -> bool {
+        self.hook.map(|hook| hook as usize) == Some(state_hook as ffi::lua_Hook as usize)
+    }
+}
+
+fn event_mask(event: c_int) -> c_int {
+    match event {
+        ffi::LUA_HOOKCALL => ffi::LUA_MASKCALL,
+        ffi::LUA_HOOKRET => ffi::LUA_MASKRET,
+        ffi::LUA_HOOKLINE => ffi::LUA_MASKLINE,
+        ffi::LUA_HOOKCOUNT => ffi::LUA_MASKCOUNT,
+        // A tail return with Lua 5.1, and a tail call with later versions.
+        #[cfg(rlua_lua51)]
+        ffi::LUA_HOOKTAILCALL => ffi::LUA_MASKRET,
+        #[cfg(not(rlua_lua51))]
+        ffi::LUA_HOOKTAILCALL => ffi::LUA_MASKCALL,
+        _ => 0,
+    }
+}
+
+/// The hook rlua keeps on a state for `interrupt_handle` and `set_max_depth`.
+///
+/// It's set on the main thread on top of whichever hook was there before, which it passes
+/// the events it asked for on to, and coroutines inherit it when they're created.  The
+/// errors are raised by calling the functions registered under `INTERRUPTED_KEY` and
+/// `TOO_DEEP_KEY` (with the depth and a traceback), so that they keep their types.
+pub(crate) struct StateHook {
+    /// Set to stop the running script at the next check.
+    pub(crate) interrupted: AtomicBool,
+    // Set once `Interrupted` has been raised, until Rust calls into the main thread again.
+    interrupting: AtomicBool,
+    interruptible: AtomicBool,
+    // Zero for no limit.
+    max_depth: AtomicUsize,
+    main: usize,
+    previous: Mutex<SavedHook>,
+}
+
+// Null terminated for use from the hook.
+pub(crate) const INTERRUPTED_KEY: &str = "rlua.hook.interrupted\0";
+pub(crate) const TOO_DEEP_KEY: &str = "rlua.hook.too_deep\0";
+const STATE_HOOK_KEY: &str = "rlua.hook.state\0";
+const OWNER_KEY: &str = "rlua.hook";
+
+/// Returns a registry key without its null terminator.
+pub(crate) fn key(key: &'static str) -> &'static str {
+    &key[..key.len() - 1]
+}
+
+// Kept in the registry, so that the `StateHook` lives as long as the state and the hook
+// can reach it through the pointer stored next to it.
+struct Owner(Arc<StateHook>);
+
+// The number of instructions between checks for interrupts, unless the previous hook
+// counts instructions itself.
+const CHECK_INTERVAL: c_int = 1000;
+
+impl StateHook {
+    /// Enables interrupts through `interrupted`.
+    pub(crate) fn enable_interrupts(lua: &Lua) -> Result<Arc<StateHook>> {
+        StateHook::configure(lua, |hook| {
+            hook.interruptible.store(true, Ordering::Relaxed)
+        })
+    }
+
+    pub(crate) fn set_max_depth(lua: &Lua, depth: Option<usize>) -> Result<()> {
+        let depth = depth.map_or(0, |depth| depth.max(1));
+        StateHook::configure(lua, |hook| hook.max_depth.store(depth, Ordering::Relaxed))?;
+        Ok(())
+    }
+
+    // Creates the hook if needed, applies the change and sets it on the main thread,
+    // unless it's already there.
+    fn configure(lua: &Lua, change: impl FnOnce(&StateHook)) -> Result<Arc<StateHook>> {
+        let state = main_state(lua)?;
+        let hook = match lua.named_registry_value::<Option<AnyUserData>>(OWNER_KEY)? {
+            Some(owner) => owner.borrow::<Owner>()?.0.clone(),
+            None => {
+                #[cfg(rlua_luajit)]
+                unsafe {
+                    disable_jit(state)
+                };
+
+                let hook = Arc::new(StateHook {
+                    interrupted: AtomicBool::new(false),
+                    interrupting: AtomicBool::new(false),
+                    interruptible: AtomicBool::new(false),
+                    max_depth: AtomicUsize::new(0),
+                    main: state as usize,
+                    previous: Mutex::new(unsafe { SavedHook::get(state) }),
+                });
+                let pointer = LightUserData(Arc::as_ptr(&hook) as *mut c_void);
+                lua.set_named_registry_value(key(STATE_HOOK_KEY), pointer)?;
+                let owner = lua.create_any_userdata(Owner(hook.clone()))?;
+                lua.set_named_registry_value(OWNER_KEY, owner)?;
+                hook
+            }
+        };
+        change(&hook);
+
+        unsafe {
+            let current = SavedHook::get(state);
+            let previous = if current.is_state_hook() {
+                *lock(&hook.previous)
+            } else {
+                *lock(&hook.previous) = current;
+                current
+            };
+            let (mask, count) = hook.events(previous);
+            ffi::lua_sethook(state, Some(state_hook), mask, count);
+        }
+        Ok(hook)
+    }
+
+    // The events to ask for, along with those of the previous hook.
+    fn events(&self, previous: SavedHook) -> (c_int, c_int) {
+        let (mut mask, mut count) = self.own_events();
+        if previous.hook.is_some() {
+            let interrupting = self.interrupting.load(Ordering::Relaxed);
+            if previous.mask & ffi::LUA_MASKCOUNT != 0 && !interrupting {
+                count = previous.count;
+            }
+            mask |= previous.mask;
+        }
+        (mask, count)
+    }
+
+    fn own_events(&self) -> (c_int, c_int) {
+        let mut mask = 0;
+        if self.interruptible.load(Ordering::Relaxed) {
+            mask |= ffi::LUA_MASKCOUNT;
+        }
+        if self.max_depth.load(Ordering::Relaxed) != 0 {
+            mask |= ffi::LUA_MASKCALL;
+        }
+        if self.interrupting.load(Ordering::Relaxed) {
+            // Every instruction, and calls to notice when Rust calls into the state again.
+            return (mask | ffi::LUA_MASKCOUNT | ffi::LUA_MASKCALL, 1);
+        }
+        (mask, CHECK_INTERVAL)
+    }
+
+    // The events to ask for on a thread.
+    fn thread_events(&self, state: *mut ffi::lua_State) -> (c_int, c_int) {
+        if state as usize == self.main {
+            self.events(*lock(&self.previous))
+        } else {
+            self.own_events()
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl StateHook {
+    // Raises an error if the event is one this hook stops scripts at.
+    unsafe fn check(&self, state: *mut ffi::lua_State, event: c_int) {
+        if event == ffi::LUA_HOOKCALL
+            && state as usize == self.main
+            && self.interrupting.load(Ordering::Relaxed)
+            && ffi::lua_getstack(state, 1, &mut mem::zeroed()) == 0
+        {
+            // Rust called into the state again, so the interrupted script is over.
+            self.interrupting.store(false, Ordering::Relaxed);
+            if SavedHook::get(state).is_state_hook() {
+                reset_thread_hook(state);
+            }
+        }
+        if event == ffi::LUA_HOOKCOUNT && self.interruptible.load(Ordering::Relaxed) {
+            let requested = self.interrupted.swap(false, Ordering::Relaxed);
+            if requested && !self.interrupting.swap(true, Ordering::Relaxed) {
+                // Check after every instruction from now on, so that the error is raised
+                // again as soon as a `pcall` catching it returns.
+                self.check_every_instruction(state);
+                if state as usize != self.main {
+                    self.check_every_instruction(self.main as *mut ffi::lua_State);
+                }
+            }
+            if requested || self.interrupting.load(Ordering::Relaxed) {
+                raise(state, INTERRUPTED_KEY, 0);
+            }
+        }
+        let max_depth = self.max_depth.load(Ordering::Relaxed);
+        if event == ffi::LUA_HOOKCALL && max_depth != 0 {
+            let mut ar: ffi::lua_Debug = mem::zeroed();
+            let level = c_int::try_from(max_depth).unwrap_or(c_int::MAX);
+            if ffi::lua_getstack(state, level, &mut ar) != 0 {
+                ffi::lua_pushinteger(state, max_depth as ffi::lua_Integer);
+                ffi::luaL_traceback(state, state, ptr::null(), 0);
+                raise(state, TOO_DEEP_KEY, 2);
+            }
+        }
+    }
+
+    // Keeps whichever hook is set on a thread, `limits_hook` included, but asks for every
+    // instruction and call.
+    unsafe fn check_every_instruction(&self, state: *mut ffi::lua_State) {
+        let current = SavedHook::get(state);
+        if current.is_state_hook() {
+            reset_thread_hook(state);
+        } else if current.hook.is_some() {
+            let mask = current.mask | ffi::LUA_MASKCOUNT | ffi::LUA_MASKCALL;
+            ffi::lua_sethook(state, current.hook, mask, 1);
+        }
+    }
+}
+
+// Returns the state's `StateHook`, if it has one.
+unsafe fn registered<'a>(state: *mut ffi::lua_State) -> Option<&'a StateHook> {
+    ffi::lua_getfield(
+        state,
+        ffi::LUA_REGISTRYINDEX,
+        STATE_HOOK_KEY.as_ptr() as *const c_char,
+    );
+    let hook = ffi::lua_touserdata(state, -1) as *const StateHook;
+    ffi::lua_pop(state, 1);
+    hook.as_ref()
+}
+
+unsafe extern "C-unwind" fn state_hook(state: *mut ffi::lua_State, ar: *mut ffi::lua_Debug) {
+    let hook = match registered(state) {
+        Some(hook) => hook,
+        None => {
+            ffi::lua_sethook(state, None, 0, 0);
+            return;
+        }
+    };
+
+    if state as usize == hook.main {
+        let previous = *lock(&hook.previous);
+        previous.forward(state, ar);
+    }
+    // A coroutine inherited the events the previous hook asked for, which only apply to the
+    // main thread, or an interrupted script has finished.
+    let current = (ffi::lua_gethookmask(state), ffi::lua_gethookcount(state));
+    if current != hook.thread_events(state) {
+        reset_thread_hook(state);
+    }
+    hook.check(state, (*ar).event);
+}
+
+/// Runs the checks of the state's `StateHook` for an event, for hooks which replace it for
+/// a while.
+pub(crate) unsafe fn check_state_hook(state: *mut ffi::lua_State, event: c_int) {
+    if let Some(hook) = registered(state) {
+        hook.check(state, event);
+    }
+}
+
+/// Sets the state's `StateHook` on a thread with the events it needs there, which on a
+/// coroutine are none of the previous hook's, or removes the hook if there isn't one.
+pub(crate) unsafe fn reset_thread_hook(state: *mut ffi::lua_State) {
+    match registered(state).map(|hook| hook.thread_events(state)) {
+        Some((mask, count)) if mask != 0 => ffi::lua_sethook(state, Some(state_hook), mask, count),
+        _ => ffi::lua_sethook(state, None, 0, 0),
+    };
+}
+
+// Calls the Rust function raising an error, with the arguments on top of the stack.
+unsafe fn raise(state: *mut ffi::lua_State, key: &str, nargs: c_int) {
+    ffi::lua_getfield(state, ffi::LUA_REGISTRYINDEX, key.as_ptr() as *const c_char);
+    ffi::lua_insert(state, -1 - nargs);
+    ffi::lua_call(state, nargs, 0);
+}
+
+/// Returns the thread `lua` is currently running on, which is where hooks have to be set.
+pub(crate) fn current_state(lua: &Lua) -> Result<*mut ffi::lua_State> {
+    unsafe extern "C-unwind" fn push_state(state: *mut ffi::lua_State) -> c_int {
+        ffi::lua_pushlightuserdata(state, state as *mut c_void);
+        1
+    }
+
+    let push_state = unsafe { lua.create_c_function(push_state)? };
+    let LightUserData(state) = push_state.call(())?;
+    Ok(state as *mut ffi::lua_State)
+}
+
+/// Returns the main thread of `lua`, which unlike a coroutine lives as long as the state.
+pub(crate) fn main_state(lua: &Lua) -> Result<*mut ffi::lua_State> {
+    let state = current_state(lua)?;
+    let is_main = unsafe {
+        let is_main = ffi::lua_pushthread(state) == 1;
+        ffi::lua_pop(state, 1);
+        is_main
+    };
+    if !is_main {
+        return Err(Error::RuntimeError(
+            "not available from within a coroutine".to_owned(),
+        ));
+    }
+    Ok(state)
+}
+
+/// Turns off the JIT compiler and flushes the compiled code.
+#[cfg(rlua_luajit)]
+pub(crate) unsafe fn disable_jit(state: *mut ffi::lua_State) {
+    const LUAJIT_MODE_ENGINE: c_int = 0;
+    const LUAJIT_MODE_OFF: c_int = 0x0000;
+    const LUAJIT_MODE_FLUSH: c_int = 0x0200;
+
+    extern "C-unwind" {
+        fn luaJIT_setmode(state: *mut ffi::lua_State, idx: c_int, mode: c_int) -> c_int;
+    }
+
+    luaJIT_setmode(state, 0, LUAJIT_MODE_ENGINE | LUAJIT_MODE_OFF);
+    luaJIT_setmode(state, 0, LUAJIT_MODE_ENGINE | LUAJIT_MODE_FLUSH);
+}
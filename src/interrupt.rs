@@ -0,0 +1,70 @@
+//! Stopping a running script from another thread.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::hook::{key, StateHook, INTERRUPTED_KEY};
+use crate::{Error, Lua, Result};
+
+/// The error raised in a script stopped through an `InterruptHandle`.
+///
+/// Find it with [`ErrorExt::find_cause`](crate::ErrorExt::find_cause).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "interrupted")
+    }
+}
+
+impl StdError for Interrupted {}
+
+/// Stops the script running in a `Lua` state, from any thread.
+///
+/// `interrupt` only sets a flag, which a count hook installed by
+/// `LuaInterruptExt::interrupt_handle` checks every 1000 instructions, raising an
+/// `Interrupted` error once it's set.  If the state isn't running anything at the time, the
+/// next script to run is interrupted instead.  `pcall` can catch `Interrupted`, but it's then
+/// raised again after every instruction until Rust next calls into the state's main thread,
+/// so the script can't carry on.  Once the state is closed, `interrupt` does nothing.
+#[derive(Clone)]
+pub struct InterruptHandle(Arc<StateHook>);
+
+impl InterruptHandle {
+    /// Stops the script running in the state.
+    pub fn interrupt(&self) {
+        self.0.interrupted.store(true, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for InterruptHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InterruptHandle").finish_non_exhaustive()
+    }
+}
+
+/// Adds `interrupt_handle` to `Lua`.
+pub trait LuaInterruptExt {
+    /// Returns a handle which can interrupt scripts running in this state from another
+    /// thread.
+    ///
+    /// The interrupt hook is set on the main thread and passes on the events of whichever
+    /// hook was set before.  It's the same hook `LuaLimitsExt::set_max_depth` uses.
+    /// Coroutines created from then on inherit it, while those created earlier can't be
+    /// interrupted.  A later `Lua::set_hook` or `Lua::remove_hook` replaces the interrupt
+    /// hook, and calling `interrupt_handle` again puts it back.
+    ///
+    /// Like `LuaLimitsExt::run_with_limits`, this turns off LuaJIT's JIT compiler.
+    fn interrupt_handle(&self) -> Result<InterruptHandle>;
+}
+
+impl LuaInterruptExt for Lua {
+    fn interrupt_handle(&self) -> Result<InterruptHandle> {
+        let raise = self.create_function(|_, ()| Err::<(), _>(Error::external(Interrupted)))?;
+        self.set_named_registry_value(key(INTERRUPTED_KEY), raise)?;
+        Ok(InterruptHandle(StateHook::enable_interrupts(self)?))
+    }
+}
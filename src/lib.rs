@@ -1,38 +1,100 @@
 pub use mlua::*;
 
-pub mod prelude {
-    pub use super::RluaCompat;
-    pub use super::ToLua;
-    pub use mlua::prelude::*;
-}
+pub mod bundle;
+pub mod bytecode;
+pub mod capability;
+mod chunk;
+pub mod deterministic;
+mod error;
+mod hook;
+pub mod interrupt;
+pub mod limits;
+#[cfg(feature = "lua-compat-mathlib")]
+mod mathlib;
+pub mod memory;
+pub mod modules;
+pub mod output;
+pub mod resolver;
+pub mod sandbox;
 
-pub type Context<'lua> = &'lua Lua;
+pub use deterministic::LuaDeterministicExt;
+pub use error::ErrorExt;
+pub use interrupt::LuaInterruptExt;
+pub use limits::LuaLimitsExt;
+pub use memory::LuaMemoryExt;
+pub use output::LuaOutputExt;
+pub use resolver::LuaResolverExt;
 
-pub trait RluaCompat {
-    #[deprecated = "Context is no longer needed; call methods on Lua directly."]
-    fn context<R, F>(&self, f: F) -> R
-    where
-        F: FnOnce(&Lua) -> R;
-}
+#[doc(hidden)]
+pub use rlua_macros::include_lua as __include_lua;
 
-impl RluaCompat for Lua {
-    fn context<R, F>(&self, f: F) -> R
-    where
-        F: FnOnce(&Lua) -> R,
-    {
-        f(self)
-    }
+/// Embeds every `.lua` file below a directory into the binary, as a
+/// [`bundle::Bundle`](crate::bundle::Bundle).
+///
+/// The path is relative to the directory containing the crate's `Cargo.toml`.  See
+/// [`Bundle`](crate::bundle::Bundle) for details.
+#[macro_export]
+macro_rules! include_lua {
+    ($dir:literal) => {
+        $crate::__include_lua!($crate, $dir)
+    };
 }
 
-pub use mlua::IntoLua as ToLua;
+#[cfg(not(feature = "strict"))]
+mod compat;
+#[cfg(not(feature = "strict"))]
+mod context;
+#[cfg(not(feature = "strict"))]
+mod userdata;
 
-pub trait ToLuaCompat<'lua> {
-    #[deprecated = "ToLua::to_lua has become IntoLua::into_lua"]
-    fn to_lua(self, context: &'lua Lua) -> mlua::Result<Value<'lua>>;
+#[cfg(not(feature = "strict"))]
+pub use compat::{
+    ChunkCompat, FromLuaMultiCompat, InitFlags, RluaCompat, ScopeCompat, ToLua, ToLuaCompat,
+    ToLuaMulti, ToLuaMultiCompat,
+};
+#[cfg(not(feature = "strict"))]
+pub use context::{Context, ContextChunk};
+#[cfg(not(feature = "strict"))]
+pub use userdata::{UserDataFieldsCompat, UserDataMethodsCompat};
+
+/// With `strict`, the compatibility helpers are left out:
+///
+/// ```compile_fail
+/// use rlua::RluaCompat;
+/// ```
+///
+/// ```compile_fail
+/// use rlua::Context;
+/// ```
+///
+/// ```compile_fail
+/// use rlua::prelude::LuaContext;
+/// ```
+#[cfg(feature = "strict")]
+mod strict {}
+
+pub mod prelude {
+    #[cfg(not(feature = "strict"))]
+    pub use super::{
+        ChunkCompat, Context as LuaContext, ContextChunk as LuaContextChunk,
+        InitFlags as LuaInitFlags, RluaCompat, ScopeCompat, ToLua, ToLuaMulti,
+        UserDataFieldsCompat, UserDataMethodsCompat,
+    };
+    pub use mlua::prelude::*;
 }
 
-impl<'lua, T: IntoLua<'lua>> ToLuaCompat<'lua> for T {
-    fn to_lua(self, context: &'lua Lua) -> mlua::Result<Value<'lua>> {
-        self.into_lua(context)
-    }
+// With `lua-no-oslib` this definition takes the place of the Lua library's own
+// `luaopen_os`, so the `os` library can never be opened: `StdLib::OS` (and so
+// `StdLib::ALL_SAFE` and `StdLib::ALL`) leave the `os` global and
+// `package.loaded.os` set to nil.
+//
+// This relies on the linker: mlua opens each library through the `luaopen_*` symbol
+// it links against, which resolves here before the Lua library.  C code inside a
+// shared Lua library calling its own `luaopen_os` (through `luaL_openlibs`, say) is
+// only redirected where the platform lets the executable interpose on the library's
+// symbols, which isn't the case on Windows or for libraries linked with `-Bsymbolic`.
+#[cfg(feature = "lua-no-oslib")]
+#[no_mangle]
+unsafe extern "C-unwind" fn luaopen_os(_state: *mut ffi::lua_State) -> std::os::raw::c_int {
+    0
 }
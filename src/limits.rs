@@ -0,0 +1,376 @@
+//! Instruction budgets and deadlines for running chunks.
+
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::error::Error as StdError;
+use std::ffi::{c_char, c_int, CStr};
+use std::time::Instant;
+use std::{fmt, mem, ptr};
+
+#[cfg(rlua_luajit)]
+use crate::hook::disable_jit;
+use crate::hook::{check_state_hook, current_state, key, reset_thread_hook, SavedHook};
+use crate::hook::{StateHook, TOO_DEEP_KEY};
+use crate::{ffi, AsChunk, Error, FromLuaMulti, Lua, Result};
+
+/// Limits on a single call to `LuaLimitsExt::run_with_limits`.
+///
+/// A limit which is `None` is not enforced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// The maximum number of Lua VM instructions to run.
+    ///
+    /// This is checked every 1000 instructions (or more often for smaller budgets), so a
+    /// chunk may run slightly past it.  Time spent inside Rust or C functions isn't counted.
+    pub instructions: Option<u64>,
+    /// The point in time after which the chunk is stopped, checked at the same interval.
+    pub deadline: Option<Instant>,
+    /// The maximum depth of nested calls made by the chunk, counting the chunk itself and
+    /// any Rust or C functions called along the way.
+    ///
+    /// This is checked on every call, so unlike Lua's own stack overflow check it doesn't
+    /// depend on the size of the stack frames.  With Lua 5.1 tail calls count towards the
+    /// depth as well.
+    pub max_depth: Option<usize>,
+}
+
+/// Which of the `Limits` a chunk ran into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LimitKind {
+    Instructions,
+    Deadline,
+}
+
+/// The error returned when a chunk run with `LuaLimitsExt::run_with_limits` exceeds its
+/// `Limits`.
+///
+/// Like the other errors rlua raises, it is found with `ErrorExt::find_cause`:
+///
+/// ```
+/// # use rlua::{ErrorExt, Lua, limits::{ExecutionLimit, LimitKind, Limits, LuaLimitsExt}};
+/// let lua = Lua::new();
+/// let limits = Limits {
+///     instructions: Some(10_000),
+///     ..Limits::default()
+/// };
+/// let err = lua
+///     .run_with_limits::<()>("while true do end", limits)
+///     .unwrap_err();
+/// assert_eq!(
+///     err.find_cause::<ExecutionLimit>(),
+///     Some(&ExecutionLimit {
+///         kind: LimitKind::Instructions
+///     })
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ExecutionLimit {
+    pub kind: LimitKind,
+}
+
+impl LimitKind {
+    // Null terminated, so that the hook can raise it without allocating.
+    fn message(self) -> &'static str {
+        match self {
+            LimitKind::Instructions => "instruction limit exceeded\0",
+            LimitKind::Deadline => "deadline exceeded\0",
+        }
+    }
+}
+
+impl fmt::Display for ExecutionLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message = self.kind.message();
+        write!(f, "{}", &message[..message.len() - 1])
+    }
+}
+
+impl StdError for ExecutionLimit {}
+
+/// The error raised when calls are nested deeper than `Limits::max_depth` or the depth set
+/// with `LuaLimitsExt::set_max_depth`.
+///
+/// Find it with [`ErrorExt::find_cause`](crate::ErrorExt::find_cause).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RecursionLimit {
+    /// The depth limit which was exceeded.
+    pub depth: usize,
+    /// The Lua traceback at the call which exceeded it.
+    pub traceback: String,
+}
+
+impl fmt::Display for RecursionLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "call depth limit of {} exceeded\n{}",
+            self.depth, self.traceback
+        )
+    }
+}
+
+impl StdError for RecursionLimit {}
+
+// Null terminated, so that the hook can raise it without allocating.
+const RECURSION_MESSAGE: &str = "call depth limit exceeded\0";
+
+/// Adds `run_with_limits` and `set_max_depth` to `Lua`.
+pub trait LuaLimitsExt {
+    /// Loads and runs a chunk, stopping it with an `ExecutionLimit` (or `RecursionLimit`)
+    /// error once it exceeds the given `Limits`.
+    ///
+    /// This installs an instruction count (and call) hook for the duration of the call, replacing
+    /// any hook set with `Lua::set_hook`, and restores the previous hook afterwards.  The
+    /// checks of `LuaInterruptExt::interrupt_handle` and `set_max_depth` keep running.  The
+    /// limit error can be caught by `pcall` within the chunk, but is raised again as soon as
+    /// the `pcall` returns.
+    ///
+    /// With LuaJIT, the JIT compiler is turned off (and stays off), as compiled code doesn't
+    /// run hooks.
+    fn run_with_limits<'lua, 'a, R>(
+        &'lua self,
+        chunk: impl AsChunk<'lua, 'a>,
+        limits: Limits,
+    ) -> Result<R>
+    where
+        R: FromLuaMulti<'lua>;
+
+    /// Limits the depth of nested calls in everything the state runs, raising a
+    /// `RecursionLimit` error on the call which exceeds it.  `None` removes the limit.
+    ///
+    /// Unlike `Limits::max_depth`, this also covers functions called from Rust with
+    /// `Function::call`.  The depth is counted from the bottom of each thread's stack, so a
+    /// coroutine has a depth of its own, and includes any Rust or C functions on it.
+    ///
+    /// The check is done by the hook `LuaInterruptExt::interrupt_handle` uses, which is set
+    /// on the main thread and passes on the events of whichever hook was set before.
+    /// Coroutines created from then on inherit it.  Those created before the hook was first
+    /// set, by `set_max_depth` or `interrupt_handle`, don't have it and so aren't limited,
+    /// while those which do pick up changes to the limit.  A later `Lua::set_hook` or
+    /// `Lua::remove_hook` replaces the hook, and calling `set_max_depth` again puts it back.
+    /// This turns off LuaJIT's JIT compiler.
+    fn set_max_depth(&self, depth: Option<usize>) -> Result<()>;
+}
+
+impl LuaLimitsExt for Lua {
+    #[track_caller]
+    fn run_with_limits<'lua, 'a, R>(
+        &'lua self,
+        chunk: impl AsChunk<'lua, 'a>,
+        limits: Limits,
+    ) -> Result<R>
+    where
+        R: FromLuaMulti<'lua>,
+    {
+        // Compile the chunk first, so that parsing it doesn't count against the limits.
+        let function = self.load(chunk).into_function()?;
+        let state = current_state(self)?;
+        #[cfg(rlua_luajit)]
+        unsafe {
+            disable_jit(state)
+        };
+
+        let guard = unsafe { HookGuard::install(state, limits) };
+        let result = function.call(());
+        match guard.finish() {
+            Some(Exceeded::Limit(kind)) => Err(Error::external(ExecutionLimit { kind })),
+            Some(Exceeded::Recursion(limit)) => Err(Error::external(limit)),
+            None => result,
+        }
+    }
+
+    fn set_max_depth(&self, depth: Option<usize>) -> Result<()> {
+        let raise = self.create_function(|_, (depth, traceback): (usize, String)| {
+            Err::<(), _>(Error::external(RecursionLimit { depth, traceback }))
+        })?;
+        self.set_named_registry_value(key(TOO_DEEP_KEY), raise)?;
+        StateHook::set_max_depth(self, depth)
+    }
+}
+
+// The most instructions run between checks of the limits.
+const CHECK_INTERVAL: u64 = 1000;
+
+thread_local! {
+    // The budgets of the calls to `run_with_limits` running on this thread, innermost
+    // last.  Hooks only run for the state which is currently executing, which is always
+    // the one the innermost call is running.
+    static BUDGETS: RefCell<Vec<Budget>> = const { RefCell::new(Vec::new()) };
+}
+
+struct Budget {
+    remaining: Option<u64>,
+    deadline: Option<Instant>,
+    // The limit on the call depth, and the stack level which exceeds it.
+    max_depth: Option<(usize, c_int)>,
+    // Whether the hook replaced is the `StateHook`, whose checks still have to run.
+    chained: bool,
+    exceeded: Option<Exceeded>,
+}
+
+enum Exceeded {
+    Limit(LimitKind),
+    Recursion(RecursionLimit),
+}
+
+enum Check {
+    Within,
+    Exceeded(&'static str),
+    TooDeep(usize),
+}
+
+impl Budget {
+    // Called from the hook every few instructions, or every instruction once an interrupt
+    // has been raised, and on every call when there is a depth limit.
+    unsafe fn check(&mut self, state: *mut ffi::lua_State, event: c_int) -> Check {
+        if self.exceeded.is_none() {
+            if event == ffi::LUA_HOOKCOUNT {
+                self.tick(ffi::lua_gethookcount(state) as u64);
+            } else if let Some((depth, level)) = self.max_depth {
+                let mut ar: ffi::lua_Debug = mem::zeroed();
+                if ffi::lua_getstack(state, level, &mut ar) != 0 {
+                    return Check::TooDeep(depth);
+                }
+            }
+        }
+        match &self.exceeded {
+            None => Check::Within,
+            Some(Exceeded::Limit(kind)) => Check::Exceeded(kind.message()),
+            Some(Exceeded::Recursion(_)) => Check::Exceeded(RECURSION_MESSAGE),
+        }
+    }
+
+    fn tick(&mut self, instructions: u64) {
+        if let Some(remaining) = &mut self.remaining {
+            *remaining = remaining.saturating_sub(instructions);
+            if *remaining == 0 {
+                self.exceeded = Some(Exceeded::Limit(LimitKind::Instructions));
+            }
+        }
+        if self.exceeded.is_none() && self.deadline.is_some_and(|d| Instant::now() >= d) {
+            self.exceeded = Some(Exceeded::Limit(LimitKind::Deadline));
+        }
+    }
+}
+
+// Returns the number of active functions on a thread's stack.
+unsafe fn stack_depth(state: *mut ffi::lua_State) -> c_int {
+    let mut ar: ffi::lua_Debug = mem::zeroed();
+    let mut level = 0;
+    while ffi::lua_getstack(state, level, &mut ar) != 0 {
+        level += 1;
+    }
+    level
+}
+
+// Installs the limits hook on a thread, and puts back the hook it replaced when finished
+// or dropped.
+struct HookGuard {
+    state: *mut ffi::lua_State,
+    previous: SavedHook,
+    finished: bool,
+}
+
+impl HookGuard {
+    unsafe fn install(state: *mut ffi::lua_State, limits: Limits) -> Self {
+        let step = limits
+            .instructions
+            .map_or(CHECK_INTERVAL, |n| n.clamp(1, CHECK_INTERVAL));
+        // Depths are counted from the function the chunk is called from.
+        let max_depth = limits.max_depth.map(|depth| {
+            let base = stack_depth(state);
+            let level = c_int::try_from(depth).unwrap_or(c_int::MAX);
+            (depth, level.saturating_add(base))
+        });
+        let previous = SavedHook::get(state);
+        let budget = Budget {
+            remaining: limits.instructions,
+            deadline: limits.deadline,
+            max_depth,
+            chained: previous.is_state_hook(),
+            exceeded: None,
+        };
+        BUDGETS.with(|budgets| budgets.borrow_mut().push(budget));
+
+        let mut mask = ffi::LUA_MASKCOUNT;
+        if max_depth.is_some() {
+            mask |= ffi::LUA_MASKCALL;
+        }
+        if previous.is_state_hook() {
+            mask |= previous.mask & ffi::LUA_MASKCALL;
+        }
+        ffi::lua_sethook(state, Some(limits_hook), mask, step as c_int);
+        HookGuard {
+            state,
+            previous,
+            finished: false,
+        }
+    }
+
+    // Restores the previous hook, returning which limit was exceeded if any.
+    fn finish(mut self) -> Option<Exceeded> {
+        self.restore()
+    }
+
+    fn restore(&mut self) -> Option<Exceeded> {
+        self.finished = true;
+        unsafe { self.previous.restore(self.state) };
+        BUDGETS.with(|budgets| budgets.borrow_mut().pop().and_then(|b| b.exceeded))
+    }
+}
+
+impl Drop for HookGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.restore();
+        }
+    }
+}
+
+unsafe extern "C-unwind" fn limits_hook(state: *mut ffi::lua_State, ar: *mut ffi::lua_Debug) {
+    let event = (*ar).event;
+    let check = BUDGETS.with(|budgets| {
+        let mut budgets = budgets.borrow_mut();
+        let budget = budgets.last_mut()?;
+        Some((budget.check(state, event), budget.chained))
+    });
+    let message = match check {
+        // A coroutine created during an earlier call kept the hook.
+        None => {
+            reset_thread_hook(state);
+            return;
+        }
+        Some((Check::Within, chained)) => {
+            if chained {
+                check_state_hook(state, event);
+            }
+            return;
+        }
+        Some((Check::Exceeded(message), _)) => message,
+        Some((Check::TooDeep(depth), _)) => {
+            let traceback = traceback(state);
+            BUDGETS.with(|budgets| {
+                if let Some(budget) = budgets.borrow_mut().last_mut() {
+                    let limit = RecursionLimit { depth, traceback };
+                    budget.exceeded = Some(Exceeded::Recursion(limit));
+                }
+            });
+            RECURSION_MESSAGE
+        }
+    };
+    // Check after every instruction from now on, so that the error is raised again as
+    // soon as a `pcall` catching it returns.
+    ffi::lua_sethook(state, Some(limits_hook), ffi::LUA_MASKCOUNT, 1);
+    // Nothing here needs dropping, so it's fine for the error to unwind past this frame.
+    ffi::lua_pushstring(state, message.as_ptr() as *const c_char);
+    ffi::lua_error(state);
+}
+
+unsafe fn traceback(state: *mut ffi::lua_State) -> String {
+    ffi::luaL_traceback(state, state, ptr::null(), 0);
+    let traceback = CStr::from_ptr(ffi::lua_tostring(state, -1))
+        .to_string_lossy()
+        .into_owned();
+    ffi::lua_pop(state, 1);
+    traceback
+}
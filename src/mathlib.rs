@@ -0,0 +1,146 @@
+use std::os::raw::c_int;
+
+use crate::{ffi, Integer};
+#[cfg(not(feature = "strict"))]
+use crate::{Lua, Result, Table, Value};
+
+// The functions from Lua's `LUA_COMPAT_MATHLIB`, with NUL terminated names.
+const FUNCTIONS: [(&str, ffi::lua_CFunction); 7] = [
+    ("cosh\0", math_cosh),
+    ("sinh\0", math_sinh),
+    ("tanh\0", math_tanh),
+    ("log10\0", math_log10),
+    ("pow\0", math_pow),
+    ("frexp\0", math_frexp),
+    ("ldexp\0", math_ldexp),
+];
+
+// With a builtin Lua this definition takes the place of the Lua library's own
+// `luaopen_math`, the same way as `luaopen_os` with `lua-no-oslib`.  `build.rs` builds
+// the math library without the C compat functions and renames its entry point to
+// `rlua_luaopen_math`, so every state gets the functions below when `math` is opened.
+#[cfg(any(feature = "builtin-lua53", feature = "builtin-lua54"))]
+#[no_mangle]
+unsafe extern "C-unwind" fn luaopen_math(state: *mut ffi::lua_State) -> c_int {
+    extern "C-unwind" {
+        fn rlua_luaopen_math(state: *mut ffi::lua_State) -> c_int;
+    }
+
+    rlua_luaopen_math(state);
+    for (name, function) in FUNCTIONS {
+        let name = name.as_ptr() as *const _;
+        if ffi::lua_getfield(state, -1, name) == ffi::LUA_TNIL {
+            ffi::lua_pushcfunction(state, function);
+            ffi::lua_setfield(state, -3, name);
+        }
+        ffi::lua_pop(state, 1);
+    }
+    1
+}
+
+// Install the functions from Lua's `LUA_COMPAT_MATHLIB` into the `math` table,
+// leaving any which the Lua library already provides alone.
+#[cfg(not(feature = "strict"))]
+pub(crate) fn load_compat_mathlib(lua: &Lua) -> Result<()> {
+    let math: Table = match lua.globals().raw_get::<_, Value>("math")? {
+        Value::Table(math) => math,
+        _ => return Ok(()),
+    };
+
+    for (name, function) in FUNCTIONS {
+        let name = &name[..name.len() - 1];
+        if math.raw_get::<_, Value>(name)?.is_nil() {
+            math.raw_set(name, unsafe { lua.create_c_function(function)? })?;
+        }
+    }
+    Ok(())
+}
+
+unsafe extern "C-unwind" fn math_cosh(state: *mut ffi::lua_State) -> c_int {
+    unary(state, f64::cosh)
+}
+
+unsafe extern "C-unwind" fn math_sinh(state: *mut ffi::lua_State) -> c_int {
+    unary(state, f64::sinh)
+}
+
+unsafe extern "C-unwind" fn math_tanh(state: *mut ffi::lua_State) -> c_int {
+    unary(state, f64::tanh)
+}
+
+unsafe extern "C-unwind" fn math_log10(state: *mut ffi::lua_State) -> c_int {
+    unary(state, f64::log10)
+}
+
+unsafe extern "C-unwind" fn math_pow(state: *mut ffi::lua_State) -> c_int {
+    let x = ffi::luaL_checknumber(state, 1);
+    let y = ffi::luaL_checknumber(state, 2);
+    ffi::lua_pushnumber(state, x.powf(y));
+    1
+}
+
+unsafe extern "C-unwind" fn math_frexp(state: *mut ffi::lua_State) -> c_int {
+    let (m, e) = frexp(ffi::luaL_checknumber(state, 1));
+    ffi::lua_pushnumber(state, m);
+    ffi::lua_pushinteger(state, e as ffi::lua_Integer);
+    2
+}
+
+unsafe extern "C-unwind" fn math_ldexp(state: *mut ffi::lua_State) -> c_int {
+    let m = ffi::luaL_checknumber(state, 1);
+    let e = ffi::luaL_checkinteger(state, 2);
+    ffi::lua_pushnumber(state, ldexp(m, e as Integer));
+    1
+}
+
+unsafe fn unary(state: *mut ffi::lua_State, f: fn(f64) -> f64) -> c_int {
+    let x = ffi::luaL_checknumber(state, 1);
+    ffi::lua_pushnumber(state, f(x));
+    1
+}
+
+// Splits `x` into a mantissa in `[0.5, 1)` and a power of two, like C's `frexp`.
+fn frexp(x: f64) -> (f64, i32) {
+    if x == 0.0 || !x.is_finite() {
+        return (x, 0);
+    }
+
+    let bits = x.to_bits();
+    let exp = ((bits >> 52) & 0x7ff) as i32;
+    if exp == 0 {
+        // Subnormal, scale into the normal range first.
+        let (m, e) = frexp(x * f64::from_bits(0x43f0_0000_0000_0000));
+        return (m, e - 64);
+    }
+
+    let m = f64::from_bits((bits & !(0x7ff << 52)) | (0x3fe << 52));
+    (m, exp - 0x3fe)
+}
+
+// Computes `x * 2^e` without intermediate overflow, like C's `ldexp`.
+fn ldexp(x: f64, e: Integer) -> f64 {
+    let two_1023 = f64::from_bits(0x7fe0_0000_0000_0000);
+    // 2^-1022 * 2^53, keeping some precision for subnormal results.
+    let two_m969 = f64::from_bits(0x0360_0000_0000_0000);
+
+    let mut y = x;
+    let mut n = e.clamp(-3000, 3000);
+    if n > 1023 {
+        y *= two_1023;
+        n -= 1023;
+        if n > 1023 {
+            y *= two_1023;
+            n -= 1023;
+            n = n.min(1023);
+        }
+    } else if n < -1022 {
+        y *= two_m969;
+        n += 1022 - 53;
+        if n < -1022 {
+            y *= two_m969;
+            n += 1022 - 53;
+            n = n.max(-1022);
+        }
+    }
+    y * f64::from_bits(((0x3ff + n) as u64) << 52)
+}
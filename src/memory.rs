@@ -0,0 +1,429 @@
+//! Accounting for the memory allocated by a single call, and caps on the size of strings
+//! and tables built by scripts.
+
+use std::convert::TryFrom;
+use std::error::Error as StdError;
+#[cfg(not(rlua_luajit))]
+use std::ffi::c_void;
+use std::fmt;
+#[cfg(not(rlua_luajit))]
+use std::ptr;
+
+#[cfg(not(rlua_luajit))]
+use crate::ffi;
+#[cfg(not(rlua_luajit))]
+use crate::hook::current_state;
+use crate::{Error, Lua, Result, Table, Value};
+
+/// The memory a call measured by `LuaMemoryExt` allocated and freed, in bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// The total size of the allocations made, including the growth of reallocated blocks.
+    pub allocated: usize,
+    /// The total size of the allocations freed, including the shrinking of reallocated
+    /// blocks.  This includes memory allocated before the call and collected during it.
+    pub freed: usize,
+    /// The most memory in use at any point during the call, over what was in use at the
+    /// start of it.
+    pub peak: usize,
+}
+
+/// Caps on the size of the values built by scripts, set with `LuaMemoryExt::set_size_limits`.
+///
+/// A limit which is `None` is not enforced.  The library functions which can build large
+/// values from small arguments are checked before they run: `string.rep` and
+/// `table.concat` for strings, and `table.insert` and `table.move` for tables.
+///
+/// Lua has no way to intercept the `..` operator or assignments to table fields, so values
+/// built that way aren't checked.  Set a memory limit with `Lua::set_memory_limit` as well
+/// to bound them, which makes a script going over it fail with `Error::MemoryError`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SizeLimits {
+    /// The longest string, in bytes, which `string.rep` and `table.concat` may return.
+    pub max_string_len: Option<usize>,
+    /// The most entries `table.insert` and `table.move` may grow a table to, counting a
+    /// table's entries by its length.
+    pub max_table_entries: Option<usize>,
+}
+
+/// Which of the `SizeLimits` a script ran into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SizeLimitKind {
+    StringLength,
+    TableEntries,
+}
+
+/// The error raised when a script would exceed its `SizeLimits`.
+///
+/// Find it with [`ErrorExt::find_cause`](crate::ErrorExt::find_cause).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SizeLimitExceeded {
+    pub kind: SizeLimitKind,
+    /// The limit which was exceeded.
+    pub limit: usize,
+    /// The size the value would have grown to.  For `table.concat` this only counts the
+    /// elements up to the one which took it over the limit.
+    pub size: usize,
+}
+
+impl fmt::Display for SizeLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            SizeLimitKind::StringLength => write!(
+                f,
+                "string of {} bytes exceeds the length limit of {}",
+                self.size, self.limit
+            ),
+            SizeLimitKind::TableEntries => write!(
+                f,
+                "table of {} entries exceeds the limit of {}",
+                self.size, self.limit
+            ),
+        }
+    }
+}
+
+impl StdError for SizeLimitExceeded {}
+
+/// Adds `measure`, `measure_with_quota` and `set_size_limits` to `Lua`.
+///
+/// The measurements work by wrapping the allocator behind `Lua::set_memory_limit`, which
+/// stays in effect, so they aren't available with LuaJIT.  While a call is being measured
+/// `Lua::set_memory_limit` returns `Error::MemoryLimitNotAvailable`.
+pub trait LuaMemoryExt {
+    /// Calls `f`, returning its result along with the memory allocated by the Lua state
+    /// while it ran.
+    fn measure<R>(&self, f: impl FnOnce() -> R) -> Result<(R, MemoryStats)>;
+
+    /// Like `measure`, but allocations which would take the memory in use more than
+    /// `quota` bytes over what was in use at the start of the call fail with
+    /// `Error::MemoryError`.
+    ///
+    /// Calls can be nested, with each quota counting the allocations of the calls inside
+    /// it.
+    fn measure_with_quota<R>(
+        &self,
+        quota: usize,
+        f: impl FnOnce() -> R,
+    ) -> Result<(R, MemoryStats)>;
+
+    /// Caps the size of the strings and tables built by the `string` and `table` libraries,
+    /// replacing the previous `SizeLimits` (by default there are none).
+    ///
+    /// The sizes are checked before any memory is allocated, so a cap lower than the memory
+    /// limit is hit first, with a `SizeLimitExceeded` error.
+    fn set_size_limits(&self, limits: SizeLimits) -> Result<()>;
+}
+
+impl LuaMemoryExt for Lua {
+    fn measure<R>(&self, f: impl FnOnce() -> R) -> Result<(R, MemoryStats)> {
+        measure(self, None, f)
+    }
+
+    fn measure_with_quota<R>(
+        &self,
+        quota: usize,
+        f: impl FnOnce() -> R,
+    ) -> Result<(R, MemoryStats)> {
+        measure(self, Some(quota), f)
+    }
+
+    fn set_size_limits(&self, limits: SizeLimits) -> Result<()> {
+        // The checks are only installed once, and read the current limits.
+        if self.app_data_ref::<CurrentSizeLimits>().is_none() {
+            install_size_checks(self)?;
+        }
+        self.set_app_data(CurrentSizeLimits(limits));
+        Ok(())
+    }
+}
+
+// The limits the checks read, kept in the state's app data under a type of our own.
+struct CurrentSizeLimits(SizeLimits);
+
+fn size_limit(lua: &Lua, kind: SizeLimitKind) -> Option<usize> {
+    let limits = lua.app_data_ref::<CurrentSizeLimits>()?.0;
+    match kind {
+        SizeLimitKind::StringLength => limits.max_string_len,
+        SizeLimitKind::TableEntries => limits.max_table_entries,
+    }
+}
+
+// Raises a `SizeLimitExceeded` error if `size` is over the current limit of its kind.
+fn check_size(lua: &Lua, kind: SizeLimitKind, size: usize) -> Result<()> {
+    match size_limit(lua, kind) {
+        Some(limit) if size > limit => {
+            Err(Error::external(SizeLimitExceeded { kind, limit, size }))
+        }
+        _ => Ok(()),
+    }
+}
+
+// The length of a value as a string, if it is one or can be converted to one.
+fn string_len(lua: &Lua, value: Value) -> Result<Option<usize>> {
+    Ok(match value {
+        Value::String(string) => Some(string.as_bytes().len()),
+        Value::Integer(_) | Value::Number(_) => lua
+            .coerce_string(value)?
+            .map(|string| string.as_bytes().len()),
+        _ => None,
+    })
+}
+
+fn to_usize(lua: &Lua, value: Value) -> Result<Option<usize>> {
+    Ok(lua.coerce_integer(value)?.map(clamp))
+}
+
+// Negative sizes count as nothing, and sizes too large for `usize` as the largest one.
+fn clamp(n: crate::Integer) -> usize {
+    if n < 0 {
+        0
+    } else {
+        usize::try_from(n).unwrap_or(usize::MAX)
+    }
+}
+
+// Each check is passed the arguments of the function it guards, and leaves any errors in
+// them for the real function to report.
+fn install_size_checks(lua: &Lua) -> Result<()> {
+    let check_rep = lua.create_function(|lua, (s, n, sep): (Value, Value, Value)| {
+        let (s_len, n) = match (string_len(lua, s)?, to_usize(lua, n)?) {
+            (Some(s_len), Some(n)) if n > 0 => (s_len, n),
+            _ => return Ok(()),
+        };
+        // Lua 5.1 has no separator argument.
+        #[cfg(all(rlua_lua51, not(rlua_luajit)))]
+        let sep_len = {
+            let _ = sep;
+            0
+        };
+        #[cfg(not(all(rlua_lua51, not(rlua_luajit))))]
+        let sep_len = string_len(lua, sep)?.unwrap_or(0);
+        let size = s_len
+            .checked_add(sep_len)
+            .and_then(|len| len.checked_mul(n))
+            .map_or(usize::MAX, |size| size - sep_len);
+        check_size(lua, SizeLimitKind::StringLength, size)
+    })?;
+
+    let check_concat = lua.create_function(
+        |lua, (list, sep, first, last): (Value, Value, Value, Value)| {
+            let list = match list {
+                Value::Table(list) if size_limit(lua, SizeLimitKind::StringLength).is_some() => {
+                    list
+                }
+                _ => return Ok(()),
+            };
+            let sep_len = string_len(lua, sep)?.unwrap_or(0);
+            let first = lua.coerce_integer(first)?.unwrap_or(1);
+            let last = match last {
+                Value::Nil => len(&list)?,
+                last => match lua.coerce_integer(last)? {
+                    Some(last) => last,
+                    None => return Ok(()),
+                },
+            };
+
+            let mut size = 0usize;
+            let mut i = first;
+            while i <= last {
+                let element = match string_len(lua, get(&list, i)?)? {
+                    Some(element) => element,
+                    None => return Ok(()),
+                };
+                let sep_len = if i < last { sep_len } else { 0 };
+                size = size.saturating_add(element).saturating_add(sep_len);
+                check_size(lua, SizeLimitKind::StringLength, size)?;
+                i += 1;
+            }
+            Ok(())
+        },
+    )?;
+
+    let check_insert = lua.create_function(|lua, list: Value| match list {
+        Value::Table(list) => {
+            let size = clamp(len(&list)?).saturating_add(1);
+            check_size(lua, SizeLimitKind::TableEntries, size)
+        }
+        _ => Ok(()),
+    })?;
+
+    let check_move = lua.create_function(
+        |lua, (list, first, last, to, dest): (Value, Value, Value, Value, Value)| {
+            let dest = match (dest, list) {
+                (Value::Table(dest), _) | (Value::Nil, Value::Table(dest)) => dest,
+                _ => return Ok(()),
+            };
+            let (first, last, to) = match (
+                lua.coerce_integer(first)?,
+                lua.coerce_integer(last)?,
+                lua.coerce_integer(to)?,
+            ) {
+                (Some(first), Some(last), Some(to)) if first <= last => (first, last, to),
+                _ => return Ok(()),
+            };
+            let end = to.saturating_add(last.saturating_sub(first));
+            let size = end.max(len(&dest)?);
+            check_size(lua, SizeLimitKind::TableEntries, clamp(size))
+        },
+    )?;
+
+    lua.load(SIZE_CHECKS_SOURCE)
+        .set_name("=rlua size limits")
+        .call::<_, ()>((check_rep, check_concat, check_insert, check_move))
+}
+
+// Lua 5.1 and LuaJIT read the elements of the table passed to `table.concat` directly,
+// while later versions go through its metamethods.
+#[cfg(rlua_lua51)]
+fn len(list: &Table) -> Result<crate::Integer> {
+    Ok(list.raw_len() as crate::Integer)
+}
+
+#[cfg(rlua_lua51)]
+fn get<'lua>(list: &Table<'lua>, i: crate::Integer) -> Result<Value<'lua>> {
+    list.raw_get(i)
+}
+
+#[cfg(not(rlua_lua51))]
+fn len(list: &Table) -> Result<crate::Integer> {
+    list.len()
+}
+
+#[cfg(not(rlua_lua51))]
+fn get<'lua>(list: &Table<'lua>, i: crate::Integer) -> Result<Value<'lua>> {
+    list.get(i)
+}
+
+// Puts the checks in front of the library functions, when they exist.
+const SIZE_CHECKS_SOURCE: &str = r#"
+    local check_rep, check_concat, check_insert, check_move = ...
+    local type = type
+
+    if type(string) == "table" and string.rep ~= nil then
+        local rep = string.rep
+        string.rep = function(...)
+            check_rep(...)
+            return rep(...)
+        end
+    end
+
+    if type(table) ~= "table" then
+        return
+    end
+    local concat, insert, move = table.concat, table.insert, table.move
+    if concat ~= nil then
+        table.concat = function(...)
+            check_concat(...)
+            return concat(...)
+        end
+    end
+    if insert ~= nil then
+        table.insert = function(list, ...)
+            check_insert(list)
+            return insert(list, ...)
+        end
+    end
+    if move ~= nil then
+        table.move = function(...)
+            check_move(...)
+            return move(...)
+        end
+    end
+"#;
+
+#[cfg(rlua_luajit)]
+fn measure<R>(
+    _lua: &Lua,
+    _quota: Option<usize>,
+    _f: impl FnOnce() -> R,
+) -> Result<(R, MemoryStats)> {
+    Err(Error::MemoryLimitNotAvailable)
+}
+
+#[cfg(not(rlua_luajit))]
+fn measure<R>(lua: &Lua, quota: Option<usize>, f: impl FnOnce() -> R) -> Result<(R, MemoryStats)> {
+    let state = current_state(lua)?;
+    let guard = unsafe { AllocatorGuard::install(state, quota) };
+    let result = f();
+    Ok((result, guard.finish()))
+}
+
+#[cfg(not(rlua_luajit))]
+struct Measured {
+    alloc: ffi::lua_Alloc,
+    ud: *mut c_void,
+    quota: Option<usize>,
+    stats: MemoryStats,
+    in_use: isize,
+}
+
+// Puts a measuring allocator in front of the state's allocator, and removes it again when
+// finished or dropped.
+#[cfg(not(rlua_luajit))]
+struct AllocatorGuard {
+    state: *mut ffi::lua_State,
+    measured: *mut Measured,
+}
+
+#[cfg(not(rlua_luajit))]
+impl AllocatorGuard {
+    unsafe fn install(state: *mut ffi::lua_State, quota: Option<usize>) -> Self {
+        let mut ud = ptr::null_mut();
+        let alloc = ffi::lua_getallocf(state, &mut ud);
+        let measured = Box::into_raw(Box::new(Measured {
+            alloc,
+            ud,
+            quota,
+            stats: MemoryStats::default(),
+            in_use: 0,
+        }));
+        ffi::lua_setallocf(state, measuring_alloc, measured as *mut c_void);
+        AllocatorGuard { state, measured }
+    }
+
+    fn finish(self) -> MemoryStats {
+        unsafe { (*self.measured).stats }
+    }
+}
+
+#[cfg(not(rlua_luajit))]
+impl Drop for AllocatorGuard {
+    fn drop(&mut self) {
+        unsafe {
+            let measured = Box::from_raw(self.measured);
+            ffi::lua_setallocf(self.state, measured.alloc, measured.ud);
+        }
+    }
+}
+
+#[cfg(not(rlua_luajit))]
+unsafe extern "C-unwind" fn measuring_alloc(
+    ud: *mut c_void,
+    ptr: *mut c_void,
+    osize: usize,
+    nsize: usize,
+) -> *mut c_void {
+    let measured = &mut *(ud as *mut Measured);
+    // For new blocks Lua passes the type of object being allocated in `osize`.
+    let old_size = if ptr.is_null() { 0 } else { osize };
+    let growth = nsize as isize - old_size as isize;
+    if let Some(quota) = measured.quota {
+        if growth > 0 && nsize != 0 && measured.in_use + growth > quota as isize {
+            return ptr::null_mut();
+        }
+    }
+
+    let new_ptr = (measured.alloc)(measured.ud, ptr, osize, nsize);
+    if new_ptr.is_null() && nsize != 0 {
+        return new_ptr;
+    }
+    measured.in_use += growth;
+    if growth > 0 {
+        measured.stats.allocated += growth as usize;
+        measured.stats.peak = measured.stats.peak.max(measured.in_use.max(0) as usize);
+    } else {
+        measured.stats.freed += -growth as usize;
+    }
+    new_ptr
+}
@@ -0,0 +1,3 @@
+//! Lua modules implemented in Rust, which a state only gets when they are registered.
+
+pub mod json;
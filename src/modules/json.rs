@@ -0,0 +1,705 @@
+//! A `json` module for encoding Lua values as JSON and decoding them again.
+//!
+//! `register` makes the module available to `require`:
+//!
+//! ```
+//! # use rlua::{Lua, Result};
+//! # fn main() -> Result<()> {
+//! let lua = Lua::new();
+//! rlua::modules::json::register(&lua)?;
+//! lua.load(
+//!     r#"
+//!         local json = require("json")
+//!         local value = json.decode('{"ports": [80, 443], "proxy": null, "tags": []}')
+//!         assert(value.ports[2] == 443 and value.proxy == json.null)
+//!         assert(json.encode(value) == '{"ports":[80,443],"proxy":null,"tags":[]}')
+//!     "#,
+//! )
+//! .exec()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The module has these fields:
+//!
+//! * `json.encode(value [, options])` returns the JSON text for `value`.  Object keys are
+//!   written in sorted order, so the output doesn't depend on the order `pairs` visits
+//!   them in.  The `options` table may set `pretty = true` to spread the output over
+//!   several lines, and `indent` to the string (or number of spaces) to indent each level
+//!   with, two spaces by default.  Indents longer than 16 bytes are refused.  The text is
+//!   built up in Lua strings, so it counts towards the memory limit set with
+//!   `Lua::set_memory_limit`.
+//! * `json.decode(text)` returns the value encoded by `text`.  Malformed input raises a
+//!   `DecodeError` giving the line and column where decoding stopped.
+//! * `json.null` stands for JSON's `null`, so that object keys and array elements which
+//!   are `null` aren't lost.  It is `Value::NULL`, the same value `LuaSerdeExt::null`
+//!   returns.
+//! * `json.array([t])` and `json.object([t])` mark a table (or a new empty one) to be
+//!   encoded as an array or as an object, replacing its metatable, and return it.
+//!
+//! Unmarked tables are encoded as arrays when their keys are exactly `1` to `n`, and as
+//! objects when their keys are all strings.  An empty table is an object, so use
+//! `json.array()` for an empty array.  Decoded arrays are marked, so they are encoded as
+//! arrays again even when empty.  With the `serde` feature the array mark is
+//! `LuaSerdeExt::array_metatable`, which `LuaSerdeExt` treats the same way.
+//!
+//! Numbers without a fraction or exponent are decoded as integers when they fit, and
+//! integers are encoded without a fraction, so they keep their subtype on Lua 5.3 and
+//! 5.4.  NaN and infinities can't be encoded.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::str;
+use std::string::String as StdString;
+
+#[cfg(feature = "serde")]
+use crate::LuaSerdeExt;
+use crate::{Error, Integer, Lua, Result, String, Table, Value};
+
+/// The error raised by `json.decode` for malformed input.
+///
+/// Find it with [`ErrorExt::find_cause`](crate::ErrorExt::find_cause).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DecodeError {
+    /// What was wrong with the input.
+    pub message: StdString,
+    /// The line where decoding stopped, counting from 1.
+    pub line: usize,
+    /// The column where decoding stopped, counting characters from 1.
+    pub column: usize,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at line {} column {}",
+            self.message, self.line, self.column
+        )
+    }
+}
+
+impl StdError for DecodeError {}
+
+/// The error raised by `json.encode` for a value which has no JSON form, such as a
+/// function, a string which isn't valid UTF-8 or a table with holes.
+///
+/// Find it with [`ErrorExt::find_cause`](crate::ErrorExt::find_cause).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EncodeError {
+    /// Why the value couldn't be encoded.
+    pub message: StdString,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot encode {}", self.message)
+    }
+}
+
+impl StdError for EncodeError {}
+
+/// Creates a new `json` module table.
+///
+/// This is useful for adding the module to an environment without `require`, such as
+/// one made by `Sandbox::create_env`.
+pub fn module<'lua>(lua: &'lua Lua) -> Result<Table<'lua>> {
+    let module = lua.create_table()?;
+    module.raw_set(
+        "encode",
+        lua.create_function(|lua, (value, options): (Value, Option<Table>)| {
+            Encoder::new(lua, options)?.encode(value)
+        })?,
+    )?;
+    module.raw_set(
+        "decode",
+        lua.create_function(|lua, text: String| Decoder::new(lua, text.as_bytes())?.decode())?,
+    )?;
+    module.raw_set("null", Value::NULL)?;
+    module.raw_set(
+        "array",
+        lua.create_function(|lua, table: Option<Table>| mark(lua, table, array_metatable(lua)?))?,
+    )?;
+    module.raw_set(
+        "object",
+        lua.create_function(|lua, table: Option<Table>| mark(lua, table, object_metatable(lua)?))?,
+    )?;
+    Ok(module)
+}
+
+/// Adds the `json` module to `package.preload`, so that `require("json")` loads it.
+pub fn register(lua: &Lua) -> Result<()> {
+    let package: Table = lua.globals().get("package")?;
+    let preload: Table = package.get("preload")?;
+    preload.set("json", lua.create_function(|lua, ()| module(lua))?)
+}
+
+// Deeper nesting is refused rather than risking the stack, which also stops the encoder
+// at cycles.
+const MAX_DEPTH: usize = 128;
+
+// Every level of nesting repeats the indent, so it's kept short.
+const MAX_INDENT: usize = 16;
+
+// The encoder moves its output into a Lua string whenever it grows this long.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[cfg(not(feature = "serde"))]
+const ARRAY_KEY: &str = "rlua.json.array";
+const OBJECT_KEY: &str = "rlua.json.object";
+
+fn mark<'lua>(
+    lua: &'lua Lua,
+    table: Option<Table<'lua>>,
+    metatable: Table<'lua>,
+) -> Result<Table<'lua>> {
+    let table = match table {
+        Some(table) => table,
+        None => lua.create_table()?,
+    };
+    table.set_metatable(Some(metatable));
+    Ok(table)
+}
+
+#[cfg(feature = "serde")]
+fn array_metatable<'lua>(lua: &'lua Lua) -> Result<Table<'lua>> {
+    Ok(lua.array_metatable())
+}
+
+#[cfg(not(feature = "serde"))]
+fn array_metatable<'lua>(lua: &'lua Lua) -> Result<Table<'lua>> {
+    marker(lua, ARRAY_KEY)
+}
+
+fn object_metatable<'lua>(lua: &'lua Lua) -> Result<Table<'lua>> {
+    marker(lua, OBJECT_KEY)
+}
+
+fn marker<'lua>(lua: &'lua Lua, key: &str) -> Result<Table<'lua>> {
+    if let Some(metatable) = lua.named_registry_value::<Option<Table>>(key)? {
+        return Ok(metatable);
+    }
+    let metatable = lua.create_table()?;
+    lua.set_named_registry_value(key, metatable.clone())?;
+    Ok(metatable)
+}
+
+#[derive(Clone, Copy)]
+enum Shape {
+    Array,
+    Object,
+}
+
+struct Encoder<'lua> {
+    lua: &'lua Lua,
+    array_mt: Table<'lua>,
+    object_mt: Table<'lua>,
+    indent: Option<Vec<u8>>,
+    out: Vec<u8>,
+    // The output so far, kept in Lua strings so that it counts towards the memory limit.
+    chunks: Table<'lua>,
+}
+
+impl<'lua> Encoder<'lua> {
+    fn new(lua: &'lua Lua, options: Option<Table<'lua>>) -> Result<Self> {
+        let mut indent = None;
+        if let Some(options) = options {
+            if options.get::<_, Option<bool>>("pretty")? == Some(true) {
+                indent = Some(b"  ".to_vec());
+            }
+            match options.get::<_, Value>("indent")? {
+                Value::Nil => {}
+                Value::String(s) if s.as_bytes().len() <= MAX_INDENT => {
+                    indent = Some(s.as_bytes().to_vec())
+                }
+                Value::Integer(n) if (0..=MAX_INDENT as Integer).contains(&n) => {
+                    indent = Some(vec![b' '; n as usize])
+                }
+                Value::String(_) | Value::Integer(_) => {
+                    return Err(Error::RuntimeError(format!(
+                        "invalid 'indent' option (more than {} bytes, or negative)",
+                        MAX_INDENT
+                    )))
+                }
+                value => {
+                    return Err(Error::RuntimeError(format!(
+                        "invalid 'indent' option (a {})",
+                        value.type_name()
+                    )))
+                }
+            }
+        }
+        Ok(Encoder {
+            lua,
+            array_mt: array_metatable(lua)?,
+            object_mt: object_metatable(lua)?,
+            indent,
+            out: Vec::new(),
+            chunks: lua.create_table()?,
+        })
+    }
+
+    fn encode(mut self, value: Value<'lua>) -> Result<String<'lua>> {
+        self.value(value, 0)?;
+        if self.chunks.raw_len() == 0 {
+            return self.lua.create_string(&self.out);
+        }
+        self.flush()?;
+        let mut text = Vec::new();
+        for chunk in self.chunks.sequence_values::<String>() {
+            text.extend_from_slice(chunk?.as_bytes());
+        }
+        self.lua.create_string(&text)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.chunks.raw_push(self.lua.create_string(&self.out)?)?;
+        self.out.clear();
+        Ok(())
+    }
+
+    fn value(&mut self, value: Value<'lua>, depth: usize) -> Result<()> {
+        if self.out.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        match value {
+            Value::Nil => self.out.extend_from_slice(b"null"),
+            value if value == Value::NULL => self.out.extend_from_slice(b"null"),
+            Value::Boolean(b) => self
+                .out
+                .extend_from_slice(if b { b"true" } else { b"false" }),
+            Value::Integer(i) => self.out.extend_from_slice(i.to_string().as_bytes()),
+            Value::Number(n) if n.is_finite() => {
+                // `Debug` writes the shortest form which reads back exactly, keeping the
+                // fraction of whole numbers and switching to an exponent for large and
+                // small ones, all of which is valid JSON.
+                self.out.extend_from_slice(format!("{:?}", n).as_bytes())
+            }
+            Value::Number(n) => return Err(encode_error(format!("the number {}", n))),
+            Value::String(s) => self.string(s.as_bytes())?,
+            Value::Table(table) => self.table(table, depth)?,
+            value => return Err(encode_error(format!("a {}", value.type_name()))),
+        }
+        Ok(())
+    }
+
+    fn table(&mut self, table: Table<'lua>, depth: usize) -> Result<()> {
+        if depth == MAX_DEPTH {
+            return Err(encode_error(format!(
+                "tables nested more than {} deep (is there a cycle?)",
+                MAX_DEPTH
+            )));
+        }
+
+        let metatable = table.get_metatable();
+        let marked = if metatable.as_ref() == Some(&self.array_mt) {
+            Some(Shape::Array)
+        } else if metatable.as_ref() == Some(&self.object_mt) {
+            Some(Shape::Object)
+        } else {
+            None
+        };
+
+        let mut indices = Vec::new();
+        let mut names = Vec::new();
+        table.for_each(|key: Value, value: Value| {
+            match key {
+                Value::Integer(i) => indices.push((i, value)),
+                Value::String(s) => names.push((s.as_bytes().to_vec(), value)),
+                key => {
+                    return Err(encode_error(format!(
+                        "a table with a {} key",
+                        key.type_name()
+                    )))
+                }
+            }
+            Ok(())
+        })?;
+
+        let shape = match marked {
+            Some(shape) => shape,
+            None if names.is_empty() && !indices.is_empty() => Shape::Array,
+            None if indices.is_empty() => Shape::Object,
+            None => {
+                return Err(encode_error(
+                    "a table with both integer and string keys (mark it with json.object)"
+                        .to_owned(),
+                ))
+            }
+        };
+
+        match shape {
+            Shape::Array => {
+                if !names.is_empty() {
+                    return Err(encode_error("an array with string keys".to_owned()));
+                }
+                indices.sort_unstable_by_key(|&(i, _)| i);
+                if indices
+                    .iter()
+                    .enumerate()
+                    .any(|(n, &(i, _))| i != n as Integer + 1)
+                {
+                    return Err(encode_error(
+                        "an array with holes (fill them with json.null)".to_owned(),
+                    ));
+                }
+                self.out.push(b'[');
+                let len = indices.len();
+                for (n, (_, value)) in indices.into_iter().enumerate() {
+                    if n > 0 {
+                        self.out.push(b',');
+                    }
+                    self.newline(depth + 1);
+                    self.value(value, depth + 1)?;
+                }
+                if len > 0 {
+                    self.newline(depth);
+                }
+                self.out.push(b']');
+            }
+            Shape::Object => {
+                names.extend(
+                    indices
+                        .into_iter()
+                        .map(|(i, value)| (i.to_string().into_bytes(), value)),
+                );
+                names.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+                self.out.push(b'{');
+                let len = names.len();
+                for (n, (name, value)) in names.into_iter().enumerate() {
+                    if n > 0 {
+                        self.out.push(b',');
+                    }
+                    self.newline(depth + 1);
+                    self.string(&name)?;
+                    self.out.push(b':');
+                    if self.indent.is_some() {
+                        self.out.push(b' ');
+                    }
+                    self.value(value, depth + 1)?;
+                }
+                if len > 0 {
+                    self.newline(depth);
+                }
+                self.out.push(b'}');
+            }
+        }
+        Ok(())
+    }
+
+    fn string(&mut self, bytes: &[u8]) -> Result<()> {
+        let s = str::from_utf8(bytes)
+            .map_err(|_| encode_error("a string which isn't valid UTF-8".to_owned()))?;
+        self.out.push(b'"');
+        for c in s.chars() {
+            match c {
+                '"' => self.out.extend_from_slice(b"\\\""),
+                '\\' => self.out.extend_from_slice(b"\\\\"),
+                '\n' => self.out.extend_from_slice(b"\\n"),
+                '\r' => self.out.extend_from_slice(b"\\r"),
+                '\t' => self.out.extend_from_slice(b"\\t"),
+                '\u{8}' => self.out.extend_from_slice(b"\\b"),
+                '\u{c}' => self.out.extend_from_slice(b"\\f"),
+                c if c < ' ' => self
+                    .out
+                    .extend_from_slice(format!("\\u{:04x}", c as u32).as_bytes()),
+                c => {
+                    let mut buf = [0; 4];
+                    self.out
+                        .extend_from_slice(c.encode_utf8(&mut buf).as_bytes())
+                }
+            }
+        }
+        self.out.push(b'"');
+        Ok(())
+    }
+
+    fn newline(&mut self, depth: usize) {
+        if let Some(indent) = &self.indent {
+            self.out.push(b'\n');
+            for _ in 0..depth {
+                self.out.extend_from_slice(indent);
+            }
+        }
+    }
+}
+
+fn encode_error(message: StdString) -> Error {
+    Error::external(EncodeError { message })
+}
+
+struct Decoder<'lua, 'a> {
+    lua: &'lua Lua,
+    array_mt: Table<'lua>,
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'lua, 'a> Decoder<'lua, 'a> {
+    fn new(lua: &'lua Lua, input: &'a [u8]) -> Result<Self> {
+        Ok(Decoder {
+            lua,
+            array_mt: array_metatable(lua)?,
+            input,
+            pos: 0,
+        })
+    }
+
+    fn decode(mut self) -> Result<Value<'lua>> {
+        if let Err(err) = str::from_utf8(self.input) {
+            self.pos = err.valid_up_to();
+            return Err(self.error("invalid UTF-8"));
+        }
+        let value = self.value(0)?;
+        self.skip_whitespace();
+        if self.pos < self.input.len() {
+            return Err(self.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value<'lua>> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(depth),
+            Some(b'[') => self.array(depth),
+            Some(b'"') => Ok(Value::String(self.lua.create_string(&self.string()?)?)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b't') => self.literal("true", Value::Boolean(true)),
+            Some(b'f') => self.literal("false", Value::Boolean(false)),
+            Some(b'n') => self.literal("null", Value::NULL),
+            Some(_) => Err(self.error("expected a value")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn object(&mut self, depth: usize) -> Result<Value<'lua>> {
+        self.enter(depth)?;
+        let table = self.lua.create_table()?;
+        self.skip_whitespace();
+        if self.eat(b'}') {
+            return Ok(Value::Table(table));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a string key"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            if !self.eat(b':') {
+                return Err(self.error("expected ':'"));
+            }
+            let value = self.value(depth + 1)?;
+            table.raw_set(self.lua.create_string(&key)?, value)?;
+            self.skip_whitespace();
+            if self.eat(b'}') {
+                return Ok(Value::Table(table));
+            }
+            if !self.eat(b',') {
+                return Err(self.error("expected ',' or '}'"));
+            }
+        }
+    }
+
+    fn array(&mut self, depth: usize) -> Result<Value<'lua>> {
+        self.enter(depth)?;
+        let table = self.lua.create_table()?;
+        table.set_metatable(Some(self.array_mt.clone()));
+        self.skip_whitespace();
+        if self.eat(b']') {
+            return Ok(Value::Table(table));
+        }
+        for i in 1.. {
+            let value = self.value(depth + 1)?;
+            table.raw_set(i, value)?;
+            self.skip_whitespace();
+            if self.eat(b']') {
+                break;
+            }
+            if !self.eat(b',') {
+                return Err(self.error("expected ',' or ']'"));
+            }
+        }
+        Ok(Value::Table(table))
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>> {
+        self.pos += 1;
+        let mut buf = Vec::new();
+        loop {
+            let start = self.pos;
+            while let Some(c) = self.peek() {
+                if c == b'"' || c == b'\\' || c < b' ' {
+                    break;
+                }
+                self.pos += 1;
+            }
+            buf.extend_from_slice(&self.input[start..self.pos]);
+
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(buf);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let escaped = match self.peek() {
+                        Some(b'u') => {
+                            self.pos += 1;
+                            self.unicode_escape()?
+                        }
+                        c => {
+                            let escaped = match c {
+                                Some(b'"') => '"',
+                                Some(b'\\') => '\\',
+                                Some(b'/') => '/',
+                                Some(b'b') => '\u{8}',
+                                Some(b'f') => '\u{c}',
+                                Some(b'n') => '\n',
+                                Some(b'r') => '\r',
+                                Some(b't') => '\t',
+                                _ => return Err(self.error("invalid escape")),
+                            };
+                            self.pos += 1;
+                            escaped
+                        }
+                    };
+                    let mut utf8 = [0; 4];
+                    buf.extend_from_slice(escaped.encode_utf8(&mut utf8).as_bytes());
+                }
+                Some(_) => return Err(self.error("control character in string")),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    // Reads the digits of a `\u` escape, and the escape for the low half of a surrogate
+    // pair.  Errors point at the start of the (first) escape.
+    fn unicode_escape(&mut self) -> Result<char> {
+        let start = self.pos - 2;
+        let high = self.hex4()?;
+        let low = match high {
+            0xd800..=0xdbff if self.input[self.pos..].starts_with(b"\\u") => {
+                self.pos += 2;
+                Some(self.hex4()?)
+            }
+            _ => None,
+        };
+        let code = match (high, low) {
+            (0xd800..=0xdbff, Some(low @ 0xdc00..=0xdfff)) => {
+                0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+            }
+            (0xd800..=0xdfff, _) => {
+                self.pos = start;
+                return Err(self.error("unpaired surrogate"));
+            }
+            (code, _) => code,
+        };
+        Ok(char::from_u32(code).unwrap())
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let digits = self
+            .input
+            .get(self.pos..self.pos + 4)
+            .and_then(|digits| str::from_utf8(digits).ok())
+            .filter(|digits| digits.bytes().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("invalid \\u escape"))?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+
+    fn number(&mut self) -> Result<Value<'lua>> {
+        let start = self.pos;
+        self.eat(b'-');
+        if !self.eat(b'0') && self.digits() == 0 {
+            return Err(self.error("invalid number"));
+        }
+        let mut integral = true;
+        if self.eat(b'.') {
+            integral = false;
+            if self.digits() == 0 {
+                return Err(self.error("invalid number"));
+            }
+        }
+        if self.eat(b'e') || self.eat(b'E') {
+            integral = false;
+            if !self.eat(b'+') {
+                self.eat(b'-');
+            }
+            if self.digits() == 0 {
+                return Err(self.error("invalid number"));
+            }
+        }
+
+        // The input is known to be ASCII here.
+        let text = str::from_utf8(&self.input[start..self.pos]).unwrap();
+        if integral {
+            if let Ok(i) = text.parse::<Integer>() {
+                return Ok(Value::Integer(i));
+            }
+        }
+        Ok(Value::Number(text.parse().unwrap()))
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while matches!(self.peek(), Some(b'0'..=b'9')) {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn literal(&mut self, word: &str, value: Value<'lua>) -> Result<Value<'lua>> {
+        if !self.input[self.pos..].starts_with(word.as_bytes()) {
+            return Err(self.error("expected a value"));
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn enter(&mut self, depth: usize) -> Result<()> {
+        if depth == MAX_DEPTH {
+            return Err(self.error(&format!("nested more than {} deep", MAX_DEPTH)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn error(&self, message: &str) -> Error {
+        let before = &self.input[..self.pos];
+        let line_start = before
+            .iter()
+            .rposition(|&c| c == b'\n')
+            .map_or(0, |i| i + 1);
+        Error::external(DecodeError {
+            message: message.to_owned(),
+            line: before.iter().filter(|&&c| c == b'\n').count() + 1,
+            // Counts the bytes which start a UTF-8 sequence.
+            column: before[line_start..]
+                .iter()
+                .filter(|&&c| c & 0xc0 != 0x80)
+                .count()
+                + 1,
+        })
+    }
+}
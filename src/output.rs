@@ -0,0 +1,225 @@
+//! Sending the output of `print` and `io.write` to Rust.
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::{Error, Function, Lua, Result, Value, Variadic};
+
+/// Which function a piece of output was written by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum OutputKind {
+    /// A line written by `print`, including its newline.
+    Print,
+    /// The arguments of a call to `io.write` or `io.stdout:write`.
+    Write,
+}
+
+/// Adds `set_output` and `set_output_fn` to `Lua`.
+///
+/// These replace `print`, `io.write` and the `write` method of files with versions which
+/// send whatever would have gone to the process's standard output to a Rust sink instead.
+/// Output is passed on as bytes, so strings which aren't valid UTF-8 arrive unchanged.
+/// Writes to other files, and `io.write` after `io.output` has switched to another file,
+/// behave as before.
+///
+/// ```
+/// # use rlua::{Lua, LuaOutputExt, Result, output::Capture};
+/// # fn main() -> Result<()> {
+/// let lua = Lua::new();
+/// let output = Capture::new();
+/// lua.set_output(output.clone())?;
+/// lua.load(r#"print("hello", 42) io.write("a", "b")"#).exec()?;
+/// assert_eq!(output.take(), b"hello\t42\nab");
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The functions are replaced in the current globals and `io` table, so a copy made
+/// beforehand (such as an environment built by a `Sandbox`) keeps the originals.
+pub trait LuaOutputExt {
+    /// Sends the output to `writer`, flushing it after each `print`.
+    fn set_output(&self, writer: impl Write + Send + 'static) -> Result<()>;
+
+    /// Sends the output to `sink`, which is called once for each `print` or `write`.
+    ///
+    /// `print` ignores errors from the sink, like Lua's own does, while `write` returns
+    /// `nil` and the error message.
+    fn set_output_fn(
+        &self,
+        sink: impl FnMut(OutputKind, &[u8]) -> io::Result<()> + Send + 'static,
+    ) -> Result<()>;
+}
+
+impl LuaOutputExt for Lua {
+    fn set_output(&self, mut writer: impl Write + Send + 'static) -> Result<()> {
+        self.set_output_fn(move |kind, bytes| {
+            writer.write_all(bytes)?;
+            if kind == OutputKind::Print {
+                writer.flush()?;
+            }
+            Ok(())
+        })
+    }
+
+    fn set_output_fn(
+        &self,
+        sink: impl FnMut(OutputKind, &[u8]) -> io::Result<()> + Send + 'static,
+    ) -> Result<()> {
+        // The replacement functions are only installed once, and read the current sink.
+        if self.app_data_ref::<Sink>().is_none() {
+            install(self)?;
+        }
+        self.set_app_data(Sink(Box::new(sink)));
+        Ok(())
+    }
+}
+
+/// A shared buffer collecting output, for example to check it in tests.
+///
+/// Clones write to the same buffer.
+#[derive(Clone, Debug, Default)]
+pub struct Capture(Arc<Mutex<Vec<u8>>>);
+
+impl Capture {
+    /// Creates an empty buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of the output collected so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.lock().clone()
+    }
+
+    /// Returns the output collected so far, leaving the buffer empty.
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.lock())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+type SinkFn = dyn FnMut(OutputKind, &[u8]) -> io::Result<()> + Send;
+
+struct Sink(Box<SinkFn>);
+
+fn emit(lua: &Lua, kind: OutputKind, bytes: &[u8]) -> io::Result<()> {
+    match lua.app_data_mut::<Sink>() {
+        Some(mut sink) => (sink.0)(kind, bytes),
+        None => Ok(()),
+    }
+}
+
+fn install(lua: &Lua) -> Result<()> {
+    let print = lua.create_function(|lua, values: Variadic<Value>| {
+        let tostring: Function = lua.globals().get("tostring")?;
+        let mut line = Vec::new();
+        for (i, value) in values.into_iter().enumerate() {
+            if i > 0 {
+                line.push(b'\t');
+            }
+            let string = match value {
+                Value::String(string) => string,
+                value => match tostring.call::<_, Value>(value)? {
+                    Value::String(string) => string,
+                    _ => {
+                        return Err(Error::RuntimeError(
+                            "'tostring' must return a string to 'print'".to_owned(),
+                        ))
+                    }
+                },
+            };
+            line.extend_from_slice(string.as_bytes());
+        }
+        line.push(b'\n');
+        let _ = emit(lua, OutputKind::Print, &line);
+        Ok(())
+    })?;
+
+    // Returns the error message if the sink failed.
+    let write = lua.create_function(|lua, values: Variadic<Value>| {
+        let mut bytes = Vec::new();
+        for (i, value) in values.into_iter().enumerate() {
+            let type_name = value.type_name();
+            let string = match value {
+                Value::String(string) => Some(string),
+                Value::Integer(_) | Value::Number(_) => lua.coerce_string(value)?,
+                _ => None,
+            };
+            match string {
+                Some(string) => bytes.extend_from_slice(string.as_bytes()),
+                None => {
+                    return Err(Error::RuntimeError(format!(
+                        "bad argument #{} to 'write' (string expected, got {})",
+                        i + 1,
+                        type_name
+                    )))
+                }
+            }
+        }
+        match emit(lua, OutputKind::Write, &bytes) {
+            Ok(()) => Ok(None),
+            Err(err) => Ok(Some(lua.create_string(err.to_string())?)),
+        }
+    })?;
+
+    lua.load(INSTALL_SOURCE)
+        .set_name("=rlua output")
+        .call::<_, ()>((print, write))
+}
+
+// Replaces `print`, `io.write` and the `write` method of files, keeping the originals for
+// files other than `io.stdout`.
+const INSTALL_SOURCE: &str = r#"
+    local print_output, write_output = ...
+    local rawequal, getmetatable, type = rawequal, getmetatable, type
+
+    print = print_output
+
+    if type(io) ~= "table" or io.stdout == nil then
+        return
+    end
+    local stdout, real_write, output = io.stdout, io.write, io.output
+
+    local function write_stdout(...)
+        local err = write_output(...)
+        if err ~= nil then
+            return nil, err
+        end
+        return stdout
+    end
+
+    if real_write ~= nil then
+        io.write = function(...)
+            if output == nil or rawequal(output(), stdout) then
+                return write_stdout(...)
+            end
+            return real_write(...)
+        end
+    end
+
+    local meta = getmetatable(stdout)
+    local methods = type(meta) == "table" and meta.__index
+    if type(methods) == "table" and methods.write ~= nil then
+        local real_file_write = methods.write
+        methods.write = function(file, ...)
+            if rawequal(file, stdout) then
+                return write_stdout(...)
+            end
+            return real_file_write(file, ...)
+        end
+    end
+"#;
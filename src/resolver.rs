@@ -0,0 +1,227 @@
+//! Loading modules and script files from somewhere other than the real filesystem.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::string::String as StdString;
+
+use crate::chunk::skip_shebang;
+use crate::{ChunkMode, Error, Function, IntoLuaMulti, Lua, MultiValue, Result, Table, Value};
+
+/// A source of files for `require`, `loadfile` and `dofile`, installed with
+/// `LuaResolverExt::set_module_resolver`.
+///
+/// Paths are relative and `/`-separated, as scripts write them.
+pub trait ModuleResolver: 'static {
+    /// Returns the contents of the file at `path`, or an error of kind
+    /// `io::ErrorKind::NotFound` if there is no such file.
+    fn read(&self, path: &str) -> io::Result<Vec<u8>>;
+}
+
+/// A `ModuleResolver` serving files from memory, for example scripts embedded in the
+/// binary with `include_bytes!`.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryResolver {
+    files: HashMap<StdString, Vec<u8>>,
+}
+
+impl MemoryResolver {
+    /// Creates a resolver with no files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file, replacing any previous file at the same path.
+    pub fn file(mut self, path: impl Into<StdString>, contents: impl Into<Vec<u8>>) -> Self {
+        self.files.insert(path.into(), contents.into());
+        self
+    }
+}
+
+impl From<HashMap<StdString, Vec<u8>>> for MemoryResolver {
+    fn from(files: HashMap<StdString, Vec<u8>>) -> Self {
+        MemoryResolver { files }
+    }
+}
+
+impl ModuleResolver for MemoryResolver {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let path = path.strip_prefix("./").unwrap_or(path);
+        match self.files.get(path) {
+            Some(contents) => Ok(contents.clone()),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+/// A `ModuleResolver` serving files from below a directory.
+///
+/// Absolute paths and paths containing `..` are refused, though symbolic links inside the
+/// directory are followed wherever they point.
+#[derive(Clone, Debug)]
+pub struct DirectoryResolver {
+    root: PathBuf,
+}
+
+impl DirectoryResolver {
+    /// Creates a resolver reading files relative to `root`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        DirectoryResolver { root: root.into() }
+    }
+}
+
+impl ModuleResolver for DirectoryResolver {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let path = Path::new(path);
+        let inside_root = path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !inside_root {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "path is outside of the root directory",
+            ));
+        }
+        std::fs::read(self.root.join(path))
+    }
+}
+
+/// Adds `set_module_resolver` to `Lua`.
+pub trait LuaResolverExt {
+    /// Makes `require`, `loadfile` and `dofile` read their files through `resolver`.
+    ///
+    /// The searcher for `package.preload` is kept, and the other searchers are replaced
+    /// with one looking for `name.lua` and then `name/init.lua`, with the dots in the module
+    /// name turned into `/`.  `package.path` and `package.cpath` are no longer used, and
+    /// neither is standard input.  Files are only loaded as source code, never as bytecode.
+    fn set_module_resolver(&self, resolver: impl ModuleResolver) -> Result<()>;
+}
+
+impl LuaResolverExt for Lua {
+    fn set_module_resolver(&self, resolver: impl ModuleResolver) -> Result<()> {
+        let resolver: Rc<dyn ModuleResolver> = Rc::new(resolver);
+        let globals = self.globals();
+
+        let file_resolver = resolver.clone();
+        let loadfile =
+            self.create_function(
+                move |lua, (filename, _mode, env): (Option<StdString>, Value, Value)| {
+                    match load_file(lua, &*file_resolver, filename, env)? {
+                        Ok(function) => Ok((Some(function), None)),
+                        Err(message) => Ok((None, Some(message))),
+                    }
+                },
+            )?;
+        let file_resolver = resolver.clone();
+        let dofile =
+            self.create_function(move |lua, filename: Option<StdString>| {
+                match load_file(lua, &*file_resolver, filename, Value::Nil)? {
+                    Ok(function) => function.call::<_, MultiValue>(()),
+                    Err(message) => Err(Error::RuntimeError(message)),
+                }
+            })?;
+        globals.set("loadfile", loadfile)?;
+        globals.set("dofile", dofile)?;
+
+        let package = match globals.get::<_, Option<Table>>("package")? {
+            Some(package) => package,
+            None => return Ok(()),
+        };
+        #[cfg(rlua_lua51)]
+        let searchers: Table = package.get("loaders")?;
+        #[cfg(not(rlua_lua51))]
+        let searchers: Table = package.get("searchers")?;
+        let searcher =
+            self.create_function(move |lua, name: StdString| search(lua, &*resolver, &name))?;
+        for i in (2..=searchers.raw_len()).rev() {
+            searchers.raw_remove(i)?;
+        }
+        searchers.raw_set(2, searcher)
+    }
+}
+
+// The file names `require` tries for a module, in order.
+const TEMPLATES: &[&str] = &["?.lua", "?/init.lua"];
+
+// Returns the loader for a module and the path it was found at, or a message listing the
+// paths tried.
+fn search<'lua>(
+    lua: &'lua Lua,
+    resolver: &dyn ModuleResolver,
+    name: &str,
+) -> Result<MultiValue<'lua>> {
+    let mut tried = StdString::new();
+    for template in TEMPLATES {
+        let path = template.replace('?', &name.replace('.', "/"));
+        match resolver.read(&path) {
+            Ok(source) => {
+                let loader = load_source(lua, &path, source, Value::Nil).map_err(|err| {
+                    let message = match err {
+                        Error::SyntaxError { message, .. } => message,
+                        err => err.to_string(),
+                    };
+                    Error::RuntimeError(format!(
+                        "error loading module '{}' from file '{}':\n\t{}",
+                        name, path, message
+                    ))
+                })?;
+                return (loader, path).into_lua_multi(lua);
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                // Lua 5.4 adds the separators between messages itself.
+                if !cfg!(rlua_lua54) || !tried.is_empty() {
+                    tried.push_str("\n\t");
+                }
+                tried.push_str(&format!("no file '{}'", path));
+            }
+            Err(err) => {
+                return Err(Error::RuntimeError(format!(
+                    "error loading module '{}' from file '{}':\n\t{}",
+                    name, path, err
+                )))
+            }
+        }
+    }
+    tried.into_lua_multi(lua)
+}
+
+// Loads a file for `loadfile` or `dofile`, returning the error message on failure like
+// `loadfile` does.
+fn load_file<'lua>(
+    lua: &'lua Lua,
+    resolver: &dyn ModuleResolver,
+    filename: Option<StdString>,
+    env: Value<'lua>,
+) -> Result<std::result::Result<Function<'lua>, StdString>> {
+    let filename = match filename {
+        Some(filename) => filename,
+        None => return Ok(Err("cannot read standard input".to_owned())),
+    };
+    let source = match resolver.read(&filename) {
+        Ok(source) => source,
+        Err(err) => return Ok(Err(format!("cannot open {}: {}", filename, err))),
+    };
+    match load_source(lua, &filename, source, env) {
+        Ok(function) => Ok(Ok(function)),
+        Err(Error::SyntaxError { message, .. }) => Ok(Err(message)),
+        Err(err) => Err(err),
+    }
+}
+
+fn load_source<'lua>(
+    lua: &'lua Lua,
+    path: &str,
+    mut source: Vec<u8>,
+    env: Value<'lua>,
+) -> Result<Function<'lua>> {
+    skip_shebang(&mut source);
+    let mut chunk = lua
+        .load(source)
+        .set_name(format!("@{}", path))
+        .set_mode(ChunkMode::Text);
+    if !env.is_nil() {
+        chunk = chunk.set_environment(env);
+    }
+    chunk.into_function()
+}
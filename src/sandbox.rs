@@ -0,0 +1,411 @@
+//! Running untrusted scripts against an allowlisted environment.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{c_char, c_int};
+use std::mem;
+use std::string::String as StdString;
+
+use crate::chunk::create_source_reader;
+use crate::{ffi, AsChunk, Chunk, ChunkMode, Error, Function, Lua, Result, Table, Value};
+
+/// Describes the globals a sandboxed script may see, and loads chunks against a fresh
+/// environment table built from them.
+///
+/// Names are either plain globals (`"print"`) or library fields (`"os.time"`, or
+/// `"string.*"` for the whole library).  Library tables are copied, so a script can't
+/// change the tables seen by the rest of the state, and `deny` removes single fields
+/// again:
+///
+/// ```
+/// # use rlua::{Lua, Result, sandbox::{self, Sandbox}};
+/// # fn main() -> Result<()> {
+/// let lua = Lua::new();
+/// sandbox::install_string_methods(&lua)?;
+/// let sandbox = Sandbox::new()
+///     .allow("assert")
+///     .allow("string.*")
+///     .deny("string.dump")
+///     .allow("math.floor");
+/// sandbox
+///     .load(&lua, "assert(string.dump == nil and ('').dump == nil and math.sin == nil)")?
+///     .exec()?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Method calls on strings use the `string` table of the sandbox the calling code runs in,
+/// so they only reach the allowed functions (and none at all when `string` isn't
+/// allowed).  This needs [`install_string_methods`] to have been called for the state
+/// before any environment is created.
+///
+/// A few globals are replaced rather than copied when allowed: `_G` refers to the sandbox
+/// environment itself, `getmetatable` returns `nil` for strings, and `load` and
+/// `loadstring` only accept source code and default to the sandbox environment.
+/// Functions which reach outside of their environment in other ways, such as `require`,
+/// `dofile`, `getfenv` or the `debug` library, are copied as they are and should not be
+/// allowed for untrusted code.
+#[derive(Clone, Debug, Default)]
+pub struct Sandbox {
+    globals: BTreeMap<StdString, Allowed>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Allowed {
+    // The whole value, or for tables every field not in `denied`.
+    all: bool,
+    fields: BTreeSet<StdString>,
+    denied: BTreeSet<StdString>,
+}
+
+impl Sandbox {
+    /// Creates a sandbox which allows no globals at all.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows a global (`"print"`), a library field (`"os.time"`) or a whole library
+    /// (`"string.*"`).
+    pub fn allow(mut self, name: &str) -> Self {
+        match name.split_once('.') {
+            Some((global, "*")) => {
+                let allowed = self.globals.entry(global.to_owned()).or_default();
+                allowed.all = true;
+            }
+            Some((global, field)) => {
+                let allowed = self.globals.entry(global.to_owned()).or_default();
+                allowed.denied.remove(field);
+                allowed.fields.insert(field.to_owned());
+            }
+            None => self.globals.entry(name.to_owned()).or_default().all = true,
+        }
+        self
+    }
+
+    /// Removes a global or a library field which was previously allowed.
+    pub fn deny(mut self, name: &str) -> Self {
+        match name.split_once('.') {
+            Some((global, field)) => {
+                if let Some(allowed) = self.globals.get_mut(global) {
+                    allowed.fields.remove(field);
+                    allowed.denied.insert(field.to_owned());
+                }
+            }
+            None => {
+                self.globals.remove(name);
+            }
+        }
+        self
+    }
+
+    /// Builds a fresh environment table holding the allowed globals.
+    ///
+    /// Values are taken from the current globals of `lua`, and names which don't exist
+    /// there are skipped.  Fails unless [`install_string_methods`] has been called for
+    /// `lua`.
+    pub fn create_env<'lua>(&self, lua: &'lua Lua) -> Result<Table<'lua>> {
+        let string_methods = string_methods(lua)?;
+        let globals = lua.globals();
+        let env = lua.create_table()?;
+        let wrappers = self.create_wrappers(lua, &env)?;
+
+        for (name, allowed) in &self.globals {
+            if name == "_G" {
+                env.raw_set("_G", env.clone())?;
+                continue;
+            }
+            if let Some(wrapper) = wrappers.raw_get::<_, Option<Function>>(name.as_str())? {
+                env.raw_set(name.as_str(), wrapper)?;
+                continue;
+            }
+
+            let value = match globals.raw_get::<_, Value>(name.as_str())? {
+                Value::Table(library) => Value::Table(copy_fields(lua, &library, allowed)?),
+                Value::Nil => continue,
+                value if allowed.all => value,
+                _ => continue,
+            };
+            env.raw_set(name.as_str(), value)?;
+        }
+
+        let methods = match env.raw_get::<_, Value>("string")? {
+            Value::Table(string) => string,
+            _ => lua.create_table()?,
+        };
+        string_methods.raw_set(env.clone(), methods)?;
+
+        Ok(env)
+    }
+
+    /// Loads a chunk of Lua source with a fresh environment from `create_env`.
+    ///
+    /// Binary chunks are refused.
+    #[track_caller]
+    pub fn load<'lua, 'a>(
+        &self,
+        lua: &'lua Lua,
+        chunk: impl AsChunk<'lua, 'a>,
+    ) -> Result<Chunk<'lua, 'a>> {
+        let env = self.create_env(lua)?;
+        Ok(lua
+            .load(chunk)
+            .set_mode(ChunkMode::Text)
+            .set_environment(env))
+    }
+
+    // Creates the replacements for the allowed globals which would otherwise give access
+    // to the real globals, keyed by name.
+    fn create_wrappers<'lua>(&self, lua: &'lua Lua, env: &Table<'lua>) -> Result<Table<'lua>> {
+        let wanted = ["getmetatable", "load", "loadstring"];
+        if !wanted.iter().any(|name| self.globals.contains_key(*name)) {
+            return lua.create_table();
+        }
+
+        let globals = lua.globals();
+        // Lua 5.1's `load` only accepts a reader function and has no mode or environment
+        // arguments, so chunks are loaded through `loadstring` and `setfenv` instead.
+        #[cfg(all(rlua_lua51, not(rlua_luajit)))]
+        let real_loadstring: Value = globals.raw_get("loadstring")?;
+        #[cfg(not(all(rlua_lua51, not(rlua_luajit))))]
+        let real_loadstring = Value::Nil;
+
+        lua.load(WRAPPERS_SOURCE).set_name("=rlua sandbox").call((
+            env.clone(),
+            globals.raw_get::<_, Value>("load")?,
+            real_loadstring,
+            globals.raw_get::<_, Value>("getmetatable")?,
+            create_source_reader(lua)?,
+            string_methods(lua)?,
+        ))
+    }
+}
+
+// Copies the allowed fields of a library table into a new table.
+fn copy_fields<'lua>(
+    lua: &'lua Lua,
+    library: &Table<'lua>,
+    allowed: &Allowed,
+) -> Result<Table<'lua>> {
+    let copy = lua.create_table()?;
+    if allowed.all {
+        for pair in library.clone().pairs::<Value, Value>() {
+            let (key, value) = pair?;
+            let denied = match &key {
+                Value::String(key) => key.to_str().is_ok_and(|key| allowed.denied.contains(key)),
+                _ => false,
+            };
+            if !denied {
+                copy.raw_set(key, value)?;
+            }
+        }
+    }
+    for field in &allowed.fields {
+        copy.raw_set(field.as_str(), library.raw_get::<_, Value>(field.as_str())?)?;
+    }
+    Ok(copy)
+}
+
+/// Routes method calls on strings through the `string` table of the sandbox the calling
+/// code runs in, which `Sandbox` needs before it can create environments for `lua`.
+///
+/// This affects the whole state, so call it before running any other code: it replaces
+/// the `__index` of the string metatable with a function, and `coroutine.create` and
+/// `coroutine.wrap` with versions which record the environment of the code creating
+/// each coroutine.  Code outside of sandboxes keeps the whole `string` library.
+///
+/// The environment is that of the nearest Lua function on the stack which has one.
+/// Every Lua 5.1 function has one, but later versions only give it to functions which
+/// use globals.  When there's none, such as for a function called straight from Rust,
+/// the environment recorded for the running coroutine is used instead, and without one
+/// strings have no methods at all.  So a function which uses no globals and runs in the
+/// main thread, or in a coroutine created from Rust, can only call string methods when
+/// it's called from Lua code.
+///
+/// Calling this again has no effect.
+pub fn install_string_methods(lua: &Lua) -> Result<()> {
+    if lua
+        .named_registry_value::<Option<Table>>(STRING_METHODS_KEY)?
+        .is_some()
+    {
+        return Ok(());
+    }
+
+    let methods = weak_table(lua)?;
+    let threads = weak_table(lua)?;
+    let metatable: Option<Table> = unsafe { lua.create_c_function(string_metatable)? }.call(())?;
+    let metatable =
+        metatable.ok_or_else(|| Error::RuntimeError("strings have no metatable".to_owned()))?;
+    let caller_env = unsafe { lua.create_c_function(caller_env)? };
+    lua.load(STRING_INDEX_SOURCE)
+        .set_name("=rlua sandbox")
+        .call::<_, ()>((
+            metatable,
+            methods.clone(),
+            threads,
+            caller_env,
+            lua.globals().raw_get::<_, Value>("coroutine")?,
+        ))?;
+
+    lua.set_named_registry_value(STRING_METHODS_KEY, methods)
+}
+
+// Returns the table mapping sandbox environments (weakly) to the `string` table their
+// method calls go through.
+fn string_methods<'lua>(lua: &'lua Lua) -> Result<Table<'lua>> {
+    lua.named_registry_value::<Option<Table>>(STRING_METHODS_KEY)?
+        .ok_or_else(|| {
+            Error::RuntimeError(
+                "sandbox::install_string_methods hasn't been called for this state".to_owned(),
+            )
+        })
+}
+
+fn weak_table<'lua>(lua: &'lua Lua) -> Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    let weak = lua.create_table()?;
+    weak.raw_set("__mode", "k")?;
+    table.set_metatable(Some(weak));
+    Ok(table)
+}
+
+const STRING_METHODS_KEY: &str = "rlua.sandbox.string_methods";
+
+// `threads` maps (weakly) each coroutine to the environment of the code which created it.
+const STRING_INDEX_SOURCE: &str = r#"
+    local metatable, methods, threads, caller_env, coroutine = ...
+    local type = type
+    local real = metatable.__index
+    if type(real) ~= "table" then
+        error("the __index of the string metatable isn't a table")
+    end
+
+    metatable.__index = function(s, key)
+        local env = caller_env(threads)
+        if env == nil then
+            return nil
+        end
+        return (methods[env] or real)[key]
+    end
+
+    if type(coroutine) ~= "table" then
+        return
+    end
+    local create, wrap, running = coroutine.create, coroutine.wrap, coroutine.running
+    if create ~= nil then
+        coroutine.create = function(f)
+            local thread = create(f)
+            threads[thread] = caller_env(threads)
+            return thread
+        end
+    end
+    if wrap ~= nil and running ~= nil then
+        coroutine.wrap = function(f)
+            if type(f) ~= "function" then
+                return wrap(f)
+            end
+            local env = caller_env(threads)
+            return wrap(function(...)
+                threads[running()] = env
+                return f(...)
+            end)
+        end
+    end
+"#;
+
+unsafe extern "C-unwind" fn string_metatable(state: *mut ffi::lua_State) -> c_int {
+    ffi::lua_pushstring(state, b"\0".as_ptr() as *const c_char);
+    ffi::lua_getmetatable(state, -1)
+}
+
+// Returns the environment of the nearest Lua function which has one, below the function
+// calling this.  Without one, returns the value for the running thread in the table passed
+// as the argument.
+unsafe extern "C-unwind" fn caller_env(state: *mut ffi::lua_State) -> c_int {
+    let mut ar: ffi::lua_Debug = mem::zeroed();
+    // Level 0 is this function and level 1 the function calling it.
+    let mut level = 2;
+    while ffi::lua_getstack(state, level, &mut ar) != 0 {
+        ffi::lua_getinfo(state, b"f\0".as_ptr() as *const c_char, &mut ar);
+        if ffi::lua_iscfunction(state, -1) == 0 && push_env(state) {
+            return 1;
+        }
+        ffi::lua_settop(state, 1);
+        level += 1;
+    }
+    ffi::lua_pushthread(state);
+    ffi::lua_rawget(state, 1);
+    1
+}
+
+// Pushes the environment of the function on top of the stack.  Every Lua 5.1 function has
+// one, while later versions only give functions which use globals an `_ENV` upvalue.
+#[cfg(rlua_lua51)]
+unsafe fn push_env(state: *mut ffi::lua_State) -> bool {
+    ffi::lua_getfenv(state, -1);
+    true
+}
+
+#[cfg(not(rlua_lua51))]
+unsafe fn push_env(state: *mut ffi::lua_State) -> bool {
+    let function = ffi::lua_gettop(state);
+    let mut n = 1;
+    loop {
+        let name = ffi::lua_getupvalue(state, function, n);
+        if name.is_null() {
+            return false;
+        }
+        if std::ffi::CStr::from_ptr(name).to_bytes() == b"_ENV" {
+            return true;
+        }
+        ffi::lua_pop(state, 1);
+        n += 1;
+    }
+}
+
+// Returns a table of the replacement globals for a sandbox environment.  Each one is only
+// created when the function it wraps exists.
+const WRAPPERS_SOURCE: &str = r##"
+    local env, real_load, real_loadstring, real_getmetatable, read_chunk, methods = ...
+    local type, select, setfenv = type, select, setfenv
+    local wrappers = {}
+
+    if real_getmetatable ~= nil then
+        wrappers.getmetatable = function(value)
+            if type(value) == "string" then
+                return nil
+            end
+            return real_getmetatable(value)
+        end
+    end
+
+    if real_loadstring ~= nil then
+        wrappers.load = function(chunk, chunkname)
+            local source, err = read_chunk("load", chunk)
+            if source == nil then
+                return nil, err
+            end
+            local func, err = real_loadstring(source, chunkname)
+            if func == nil then
+                return nil, err
+            end
+            return setfenv(func, env)
+        end
+        wrappers.loadstring = wrappers.load
+    elseif real_load ~= nil then
+        wrappers.load = function(chunk, chunkname, mode, ...)
+            local chunk_env = env
+            if select("#", ...) > 0 then
+                chunk_env = ...
+                -- Strings keep the methods of the sandbox in the custom environment.
+                if type(chunk_env) == "table" and methods[chunk_env] == nil then
+                    methods[chunk_env] = methods[env]
+                end
+            end
+            return real_load(chunk, chunkname, "t", chunk_env)
+        end
+        if loadstring ~= nil then
+            wrappers.loadstring = wrappers.load
+        end
+    end
+
+    return wrappers
+"##;
@@ -0,0 +1,236 @@
+use crate::{
+    AnyUserData, Context, FromLua, FromLuaMulti, IntoLua, IntoLuaMulti, Result, UserDataFields,
+    UserDataMethods,
+};
+
+/// Versions of the `UserDataMethods` methods which accept 0.19-style callbacks, taking a
+/// `Context` rather than `&Lua` as their first argument.
+///
+/// This lets the methods of a `UserData` impl be ported one at a time: call
+/// `add_context_method` where the body still expects a `Context`, and switch back to
+/// `add_method` once it has been updated.
+pub trait UserDataMethodsCompat<'lua, T> {
+    #[deprecated = "callbacks now take &Lua; use UserDataMethods::add_method"]
+    fn add_context_method<M, A, R>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: Fn(Context<'lua>, &T, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>;
+
+    #[deprecated = "callbacks now take &Lua; use UserDataMethods::add_method_mut"]
+    fn add_context_method_mut<M, A, R>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: FnMut(Context<'lua>, &mut T, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>;
+
+    #[deprecated = "callbacks now take &Lua; use UserDataMethods::add_function"]
+    fn add_context_function<F, A, R>(&mut self, name: impl AsRef<str>, function: F)
+    where
+        F: Fn(Context<'lua>, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>;
+
+    #[deprecated = "callbacks now take &Lua; use UserDataMethods::add_function_mut"]
+    fn add_context_function_mut<F, A, R>(&mut self, name: impl AsRef<str>, function: F)
+    where
+        F: FnMut(Context<'lua>, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>;
+
+    #[deprecated = "callbacks now take &Lua; use UserDataMethods::add_meta_method"]
+    fn add_context_meta_method<M, A, R>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: Fn(Context<'lua>, &T, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>;
+
+    #[deprecated = "callbacks now take &Lua; use UserDataMethods::add_meta_method_mut"]
+    fn add_context_meta_method_mut<M, A, R>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: FnMut(Context<'lua>, &mut T, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>;
+
+    #[deprecated = "callbacks now take &Lua; use UserDataMethods::add_meta_function"]
+    fn add_context_meta_function<F, A, R>(&mut self, name: impl AsRef<str>, function: F)
+    where
+        F: Fn(Context<'lua>, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>;
+
+    #[deprecated = "callbacks now take &Lua; use UserDataMethods::add_meta_function_mut"]
+    fn add_context_meta_function_mut<F, A, R>(&mut self, name: impl AsRef<str>, function: F)
+    where
+        F: FnMut(Context<'lua>, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>;
+}
+
+impl<'lua, T, U> UserDataMethodsCompat<'lua, T> for U
+where
+    U: UserDataMethods<'lua, T> + ?Sized,
+{
+    fn add_context_method<M, A, R>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: Fn(Context<'lua>, &T, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.add_method(name, move |lua, this, args| {
+            method(Context::from(lua), this, args)
+        });
+    }
+
+    fn add_context_method_mut<M, A, R>(&mut self, name: impl AsRef<str>, mut method: M)
+    where
+        M: FnMut(Context<'lua>, &mut T, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.add_method_mut(name, move |lua, this, args| {
+            method(Context::from(lua), this, args)
+        });
+    }
+
+    fn add_context_function<F, A, R>(&mut self, name: impl AsRef<str>, function: F)
+    where
+        F: Fn(Context<'lua>, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.add_function(name, move |lua, args| function(Context::from(lua), args));
+    }
+
+    fn add_context_function_mut<F, A, R>(&mut self, name: impl AsRef<str>, mut function: F)
+    where
+        F: FnMut(Context<'lua>, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.add_function_mut(name, move |lua, args| function(Context::from(lua), args));
+    }
+
+    fn add_context_meta_method<M, A, R>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: Fn(Context<'lua>, &T, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.add_meta_method(name, move |lua, this, args| {
+            method(Context::from(lua), this, args)
+        });
+    }
+
+    fn add_context_meta_method_mut<M, A, R>(&mut self, name: impl AsRef<str>, mut method: M)
+    where
+        M: FnMut(Context<'lua>, &mut T, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.add_meta_method_mut(name, move |lua, this, args| {
+            method(Context::from(lua), this, args)
+        });
+    }
+
+    fn add_context_meta_function<F, A, R>(&mut self, name: impl AsRef<str>, function: F)
+    where
+        F: Fn(Context<'lua>, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.add_meta_function(name, move |lua, args| function(Context::from(lua), args));
+    }
+
+    fn add_context_meta_function_mut<F, A, R>(&mut self, name: impl AsRef<str>, mut function: F)
+    where
+        F: FnMut(Context<'lua>, A) -> Result<R> + Send + 'static,
+        A: FromLuaMulti<'lua>,
+        R: IntoLuaMulti<'lua>,
+    {
+        self.add_meta_function_mut(name, move |lua, args| function(Context::from(lua), args));
+    }
+}
+
+/// Versions of the `UserDataFields` methods which accept callbacks taking a `Context`
+/// rather than `&Lua`, for use alongside `UserDataMethodsCompat`.
+pub trait UserDataFieldsCompat<'lua, T> {
+    #[deprecated = "callbacks now take &Lua; use UserDataFields::add_field_method_get"]
+    fn add_context_field_method_get<M, R>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: Fn(Context<'lua>, &T) -> Result<R> + Send + 'static,
+        R: IntoLua<'lua>;
+
+    #[deprecated = "callbacks now take &Lua; use UserDataFields::add_field_method_set"]
+    fn add_context_field_method_set<M, A>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: FnMut(Context<'lua>, &mut T, A) -> Result<()> + Send + 'static,
+        A: FromLua<'lua>;
+
+    #[deprecated = "callbacks now take &Lua; use UserDataFields::add_field_function_get"]
+    fn add_context_field_function_get<F, R>(&mut self, name: impl AsRef<str>, function: F)
+    where
+        F: Fn(Context<'lua>, AnyUserData<'lua>) -> Result<R> + Send + 'static,
+        R: IntoLua<'lua>;
+
+    #[deprecated = "callbacks now take &Lua; use UserDataFields::add_field_function_set"]
+    fn add_context_field_function_set<F, A>(&mut self, name: impl AsRef<str>, function: F)
+    where
+        F: FnMut(Context<'lua>, AnyUserData<'lua>, A) -> Result<()> + Send + 'static,
+        A: FromLua<'lua>;
+
+    #[deprecated = "callbacks now take &Lua; use UserDataFields::add_meta_field_with"]
+    fn add_context_meta_field_with<F, R>(&mut self, name: impl AsRef<str>, f: F)
+    where
+        F: Fn(Context<'lua>) -> Result<R> + Send + 'static,
+        R: IntoLua<'lua>;
+}
+
+impl<'lua, T, U> UserDataFieldsCompat<'lua, T> for U
+where
+    U: UserDataFields<'lua, T> + ?Sized,
+{
+    fn add_context_field_method_get<M, R>(&mut self, name: impl AsRef<str>, method: M)
+    where
+        M: Fn(Context<'lua>, &T) -> Result<R> + Send + 'static,
+        R: IntoLua<'lua>,
+    {
+        self.add_field_method_get(name, move |lua, this| method(Context::from(lua), this));
+    }
+
+    fn add_context_field_method_set<M, A>(&mut self, name: impl AsRef<str>, mut method: M)
+    where
+        M: FnMut(Context<'lua>, &mut T, A) -> Result<()> + Send + 'static,
+        A: FromLua<'lua>,
+    {
+        self.add_field_method_set(name, move |lua, this, value| {
+            method(Context::from(lua), this, value)
+        });
+    }
+
+    fn add_context_field_function_get<F, R>(&mut self, name: impl AsRef<str>, function: F)
+    where
+        F: Fn(Context<'lua>, AnyUserData<'lua>) -> Result<R> + Send + 'static,
+        R: IntoLua<'lua>,
+    {
+        self.add_field_function_get(name, move |lua, ud| function(Context::from(lua), ud));
+    }
+
+    fn add_context_field_function_set<F, A>(&mut self, name: impl AsRef<str>, mut function: F)
+    where
+        F: FnMut(Context<'lua>, AnyUserData<'lua>, A) -> Result<()> + Send + 'static,
+        A: FromLua<'lua>,
+    {
+        self.add_field_function_set(name, move |lua, ud, value| {
+            function(Context::from(lua), ud, value)
+        });
+    }
+
+    fn add_context_meta_field_with<F, R>(&mut self, name: impl AsRef<str>, f: F)
+    where
+        F: Fn(Context<'lua>) -> Result<R> + Send + 'static,
+        R: IntoLua<'lua>,
+    {
+        self.add_meta_field_with(name, move |lua| f(Context::from(lua)));
+    }
+}
@@ -0,0 +1,83 @@
+use std::fs;
+use std::path::PathBuf;
+
+use rlua::bytecode::Cache;
+use rlua::Lua;
+
+fn cache_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rlua-bytecode-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn entries(cache: &Cache) -> Vec<PathBuf> {
+    fs::read_dir(cache.dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect()
+}
+
+#[test]
+fn load_and_get() {
+    let cache = Cache::new(cache_dir("load"), b"key".to_vec());
+    let lua = Lua::new();
+    assert!(cache.get(&lua, "=chunk", "return 1 + 1").is_none());
+
+    let f = cache.load(&lua, "=chunk", "return 1 + 1").unwrap();
+    assert_eq!(f.call::<_, i32>(()).unwrap(), 2);
+    assert_eq!(entries(&cache).len(), 1);
+
+    let f = cache.get(&lua, "=chunk", "return 1 + 1").unwrap();
+    assert_eq!(f.call::<_, i32>(()).unwrap(), 2);
+    let f = cache.load(&lua, "=chunk", "return 1 + 1").unwrap();
+    assert_eq!(f.call::<_, i32>(()).unwrap(), 2);
+    assert_eq!(entries(&cache).len(), 1);
+
+    // Entries are keyed by both the chunk name and the source.
+    assert!(cache.get(&lua, "=other", "return 1 + 1").is_none());
+    assert!(cache.get(&lua, "=chunk", "return 1 + 2").is_none());
+
+    // Only the right key is trusted.
+    let other_key = Cache::new(cache.dir(), b"other key".to_vec());
+    assert!(other_key.get(&lua, "=chunk", "return 1 + 1").is_none());
+
+    assert!(cache.load(&lua, "=broken", "return {").is_err());
+    assert_eq!(entries(&cache).len(), 1);
+
+    fs::remove_dir_all(cache.dir()).unwrap();
+}
+
+#[test]
+fn tampered_entries() {
+    let cache = Cache::new(cache_dir("tampered"), b"key".to_vec());
+    let lua = Lua::new();
+    cache.load(&lua, "=chunk", "return 'cached'").unwrap();
+    let path = entries(&cache).pop().unwrap();
+    let original = fs::read(&path).unwrap();
+
+    // Flipping any byte of the bytecode invalidates the entry, and loading compiles the
+    // source again and replaces it.
+    let mut tampered = original.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    fs::write(&path, &tampered).unwrap();
+    assert!(cache.get(&lua, "=chunk", "return 'cached'").is_none());
+    let f = cache.load(&lua, "=chunk", "return 'cached'").unwrap();
+    assert_eq!(f.call::<_, String>(()).unwrap(), "cached");
+    assert_eq!(fs::read(&path).unwrap(), original);
+
+    fs::write(&path, &original[..original.len() / 2]).unwrap();
+    assert!(cache.get(&lua, "=chunk", "return 'cached'").is_none());
+
+    // A validly signed entry does not verify for another source.
+    cache.load(&lua, "=chunk", "return 'other'").unwrap();
+    let other_path = entries(&cache)
+        .into_iter()
+        .find(|entry| *entry != path)
+        .unwrap();
+    fs::write(&path, fs::read(&other_path).unwrap()).unwrap();
+    assert!(cache.get(&lua, "=chunk", "return 'cached'").is_none());
+    let f = cache.load(&lua, "=chunk", "return 'cached'").unwrap();
+    assert_eq!(f.call::<_, String>(()).unwrap(), "cached");
+
+    fs::remove_dir_all(cache.dir()).unwrap();
+}
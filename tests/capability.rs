@@ -0,0 +1,193 @@
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use rlua::capability::{CapabilityDenied, HostApi};
+use rlua::sandbox::Sandbox;
+use rlua::{Error, ErrorExt, Function, Lua, String as LuaString, Table, Value};
+
+fn host_api<'lua>(lua: &'lua Lua, calls: Arc<AtomicUsize>) -> HostApi<'lua> {
+    HostApi::new(lua)
+        .function("fs.read", "fs.read", |_, path: String| {
+            Ok(format!("contents of {}", path))
+        })
+        .unwrap()
+        .function(
+            "fs.write",
+            "fs.write",
+            move |_, (_, data): (String, String)| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Ok(data.len())
+            },
+        )
+        .unwrap()
+        .function("fetch", "net", |_, ()| Ok("response"))
+        .unwrap()
+}
+
+#[test]
+fn test_granted_capabilities() {
+    let lua = Lua::new();
+    let calls = Arc::new(AtomicUsize::new(0));
+    let api = host_api(&lua, calls.clone());
+    assert_eq!(
+        api.functions().collect::<Vec<_>>(),
+        [
+            ("fetch", "net"),
+            ("fs.read", "fs.read"),
+            ("fs.write", "fs.write")
+        ]
+    );
+
+    api.load(
+        r#"
+            assert(fs.read("a.txt") == "contents of a.txt")
+            assert(fetch() == "response")
+            assert(type(string.format) == "function")
+
+            local ok, err = pcall(fs.write, "a.txt", "data")
+            assert(not ok and tostring(err):find("fs.write requires the 'fs.write' capability"))
+        "#,
+        ["fs.read", "net"],
+    )
+    .unwrap()
+    .exec()
+    .unwrap();
+    assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+    // A capability covers the ones below it.
+    let written: usize = api
+        .load("return fs.write('a.txt', 'data')", ["fs"])
+        .unwrap()
+        .eval()
+        .unwrap();
+    assert_eq!(written, 4);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    let err = api
+        .load("fetch()", ["fs", "network"])
+        .unwrap()
+        .exec()
+        .unwrap_err();
+    assert_eq!(
+        err.find_cause::<CapabilityDenied>(),
+        Some(&CapabilityDenied {
+            function: "fetch".to_owned(),
+            capability: "net".to_owned(),
+        })
+    );
+
+    // The functions don't leak into the globals.
+    lua.load("assert(fs == nil and fetch == nil)")
+        .exec()
+        .unwrap();
+}
+
+#[test]
+fn test_install_into_sandbox() {
+    let lua = Lua::new();
+    rlua::sandbox::install_string_methods(&lua).unwrap();
+    let api = host_api(&lua, Arc::new(AtomicUsize::new(0)));
+    let env = Sandbox::new()
+        .allow("assert")
+        .allow("type")
+        .create_env(&lua)
+        .unwrap();
+    api.install(&env, Vec::<String>::new()).unwrap();
+
+    let err = lua
+        .load(
+            r#"
+                assert(print == nil and type(fs.read) == "function")
+                fs.read("a.txt")
+            "#,
+        )
+        .set_environment(env)
+        .exec()
+        .unwrap_err();
+    assert!(err.find_cause::<CapabilityDenied>().is_some(), "{:?}", err);
+
+    // Argument errors are reported as usual.
+    let err = api.load("fs.read({})", ["fs"]).unwrap().exec().unwrap_err();
+    assert!(err.find_cause::<CapabilityDenied>().is_none());
+    match err {
+        Error::CallbackError { cause, .. } => match *cause {
+            Error::BadArgument {
+                pos: 1, ref cause, ..
+            } => match **cause {
+                Error::FromLuaConversionError { .. } => {}
+                ref cause => panic!("unexpected cause {:?}", cause),
+            },
+            ref cause => panic!("unexpected cause {:?}", cause),
+        },
+        err => panic!("unexpected error {:?}", err),
+    }
+}
+
+#[test]
+fn test_lua_arguments() {
+    let lua = Lua::new();
+    let api = HostApi::new(&lua)
+        .function("map", "table", |lua, (t, f): (Table, Function)| {
+            let mapped = lua.create_table()?;
+            for value in t.sequence_values::<Value>() {
+                mapped.raw_push(f.call::<_, Value>(value?)?)?;
+            }
+            Ok(mapped)
+        })
+        .unwrap()
+        .function("upper", "string", |lua, s: LuaString| {
+            lua.create_string(s.as_bytes().to_ascii_uppercase())
+        })
+        .unwrap();
+
+    api.load(
+        r#"
+            local t = map({1, 2, 3}, function(x) return x * 10 end)
+            assert(#t == 3 and t[1] == 10 and t[3] == 30)
+            assert(upper("abc") == "ABC")
+        "#,
+        ["table", "string"],
+    )
+    .unwrap()
+    .exec()
+    .unwrap();
+}
+
+#[test]
+fn test_names_within_existing_tables() {
+    let lua = Lua::new();
+    // Functions only need to be `'static`, as with `Lua::create_function`.
+    let secret = Rc::new("secret".to_owned());
+    let api = HostApi::new(&lua)
+        .function(
+            "string.secret",
+            "secret",
+            move |_, ()| Ok((*secret).clone()),
+        )
+        .unwrap();
+
+    // The real `string` library is still reachable, and isn't changed.
+    api.load(
+        r#"
+            assert(string.secret() == "secret")
+            assert(string.format("%d", 1) == "1")
+        "#,
+        ["secret"],
+    )
+    .unwrap()
+    .exec()
+    .unwrap();
+    lua.load("assert(string.secret == nil)").exec().unwrap();
+
+    let api = api
+        .function("print.twice", "print", |_, ()| Ok(()))
+        .unwrap();
+    let err = api.install(&lua.globals(), ["print"]).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("'print' is a function, not a table"),
+        "{}",
+        err
+    );
+}
@@ -0,0 +1,147 @@
+use std::time::Duration;
+
+use rlua::deterministic::VirtualClock;
+use rlua::output::Capture;
+use rlua::{Lua, LuaDeterministicExt, LuaOutputExt};
+
+const SCRIPT: &str = r#"
+    print(math.random(), math.random(10), math.random(-5, 5), math.random(3, 3))
+
+    local t = { banana = 1, apple = 2, cherry = 3, Zebra = 4, [10] = "x", [2] = "y", [2.5] = "w" }
+    t[true], t[false] = "z", "f"
+    t.date = "d"
+    t.apple = nil
+    for k, v in pairs(t) do
+        io.write(tostring(k), "=", tostring(v), " ")
+    end
+    print()
+
+    print(os.time())
+    print(os.time({ year = 2000, month = 1, day = 1, hour = 0 }))
+    print(os.time({ year = 2023, month = 14, day = 1, hour = 0 }))
+"#;
+
+#[cfg(not(feature = "lua-no-oslib"))]
+const DATE_SCRIPT: &str = r#"
+    print(os.date("%Y-%m-%d %H:%M:%S"), os.date("*t").hour)
+"#;
+
+fn run(seed: u64) -> Vec<u8> {
+    let clock = VirtualClock::new(1_700_000_000);
+    let lua = Lua::new_deterministic(seed, clock.clone()).unwrap();
+    let output = Capture::new();
+    lua.set_output(output.clone()).unwrap();
+
+    lua.load(SCRIPT).exec().unwrap();
+    #[cfg(not(feature = "lua-no-oslib"))]
+    lua.load(DATE_SCRIPT).exec().unwrap();
+    clock.advance(Duration::from_millis(1500));
+    lua.load("print(os.time(), os.clock() == 1.5)")
+        .exec()
+        .unwrap();
+    output.take()
+}
+
+#[test]
+fn same_output_every_run() {
+    let first = run(1);
+    assert_eq!(first, run(1));
+    assert_ne!(first, run(2));
+
+    let mut expected = vec![
+        "0.56656157517228\t8\t5\t3",
+        "2=y 2.5=w 10=x Zebra=4 banana=1 cherry=3 date=d false=f true=z ",
+        "1700000000",
+        "946684800",
+        "1706745600",
+    ];
+    #[cfg(not(feature = "lua-no-oslib"))]
+    expected.push("2023-11-14 22:13:20\t22");
+    expected.push("1700000001\ttrue");
+    let output = String::from_utf8(first).unwrap();
+    assert_eq!(output.lines().collect::<Vec<_>>(), expected);
+}
+
+#[cfg(feature = "lua-no-oslib")]
+#[test]
+fn clock_without_oslib() {
+    let lua = Lua::new_deterministic(0, VirtualClock::new(60)).unwrap();
+    lua.load(
+        r#"
+            assert(os.time() == 60 and os.clock() == 0)
+            assert(os.date == nil and os.execute == nil and os.getenv == nil)
+        "#,
+    )
+    .exec()
+    .unwrap();
+}
+
+#[test]
+fn random() {
+    let lua = Lua::new_deterministic(42, VirtualClock::new(0)).unwrap();
+    lua.load(
+        r#"
+            for _ = 1, 1000 do
+                local x = math.random()
+                assert(x >= 0 and x < 1)
+                local n = math.random(6)
+                assert(n >= 1 and n <= 6 and n % 1 == 0)
+                n = math.random(-3, 3)
+                assert(n >= -3 and n <= 3 and n % 1 == 0)
+            end
+            assert(not pcall(math.random, 2, 1))
+
+            math.randomseed(7)
+            local a, b = math.random(1000), math.random(1000)
+            math.randomseed(7)
+            assert(math.random(1000) == a and math.random(1000) == b)
+        "#,
+    )
+    .exec()
+    .unwrap();
+
+    // Going back to the original seed repeats the sequence from the start.
+    let first: Vec<i64> = lua
+        .load("return { math.random(1000), math.random(1000) }")
+        .eval()
+        .unwrap();
+    let again: Vec<i64> = lua
+        .load("math.randomseed() return { math.random(1000), math.random(1000) }")
+        .eval()
+        .unwrap();
+    let fresh = Lua::new_deterministic(42, VirtualClock::new(0)).unwrap();
+    let expected: Vec<i64> = fresh
+        .load("return { math.random(1000), math.random(1000) }")
+        .eval()
+        .unwrap();
+    assert_eq!(again, expected);
+    assert_ne!(first, expected);
+}
+
+#[test]
+fn pairs_respects_pairs_metamethod() {
+    let lua = Lua::new_deterministic(0, VirtualClock::new(0)).unwrap();
+    // Lua 5.1 has no `__pairs`.
+    #[cfg(not(rlua_lua51))]
+    lua.load(
+        r#"
+            local t = setmetatable({}, { __pairs = function(t) return function() end, t, nil end })
+            for _ in pairs(t) do error("visited") end
+        "#,
+    )
+    .exec()
+    .unwrap();
+    lua.load(
+        r#"
+            local t = {}
+            for i = 1, 100 do t["k" .. i] = i end
+            local previous
+            for k in pairs(t) do
+                assert(previous == nil or previous < k)
+                previous = k
+            end
+        "#,
+    )
+    .exec()
+    .unwrap();
+}
@@ -1,4 +1,7 @@
-use rlua::{Function, Lua, RluaCompat, String};
+use rlua::{
+    Context, FromLuaMultiCompat, Function, Lua, MultiValue, Result, RluaCompat, String, ToLuaMulti,
+    ToLuaMultiCompat, Variadic,
+};
 
 #[test]
 fn test_function() {
@@ -72,3 +75,33 @@ fn test_rust_function() {
         assert_eq!(lua_function.call::<_, String>(()).unwrap(), "hello");
     });
 }
+
+#[test]
+fn test_multi_compat() {
+    fn bind_all<'lua, A: ToLuaMulti<'lua>>(f: Function<'lua>, args: A) -> Result<Function<'lua>> {
+        f.bind(args)
+    }
+
+    fn sum_args<'lua>(ctx: Context<'lua>, args: MultiValue<'lua>) -> Result<i64> {
+        let values = FromLuaMultiCompat::from_lua_multi(args, ctx)?;
+        let values: Variadic<i64> = values;
+        Ok(values.iter().sum())
+    }
+
+    Lua::new().context(|lua| {
+        let values = (1, "two", false).to_lua_multi(lua).unwrap();
+        assert_eq!(values.len(), 3);
+        let (a, b, c): (i64, String, bool) =
+            FromLuaMultiCompat::from_lua_multi(values, lua).unwrap();
+        assert_eq!(a, 1);
+        assert_eq!(b, "two");
+        assert!(!c);
+
+        let sum = lua
+            .create_function(|ctx, args: MultiValue| sum_args(ctx, args))
+            .unwrap();
+        let sum = bind_all(sum, (1, 2)).unwrap();
+        let sum = bind_all(sum, 3).unwrap();
+        assert_eq!(sum.call::<_, i64>((4, 5)).unwrap(), 15);
+    });
+}
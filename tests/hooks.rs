@@ -1,8 +1,14 @@
 use std::cell::RefCell;
 use std::ops::Deref;
 use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use rlua::{Error, HookTriggers, Lua, RluaCompat, Value};
+use rlua::interrupt::{InterruptHandle, Interrupted};
+use rlua::limits::{ExecutionLimit, LimitKind, Limits, RecursionLimit};
+use rlua::{
+    Error, ErrorExt, Function, HookTriggers, Lua, LuaInterruptExt, LuaLimitsExt, RluaCompat, Value,
+};
 
 #[cfg(not(rlua_luajit))] // LuaJIT gives different results
 #[test]
@@ -243,3 +249,348 @@ fn hook_swap_within_hook() {
         });
     });
 }
+
+#[test]
+fn run_with_limits() {
+    let lua = Lua::new();
+    let instructions = Limits {
+        instructions: Some(10000),
+        ..Default::default()
+    };
+
+    let err = lua
+        .run_with_limits::<()>("while true do end", instructions)
+        .expect_err("instruction limit didn't occur");
+    assert_eq!(
+        err.find_cause::<ExecutionLimit>(),
+        Some(&ExecutionLimit {
+            kind: LimitKind::Instructions
+        })
+    );
+
+    // The limit error can't be swallowed by `pcall`.
+    let err = lua
+        .run_with_limits::<()>(
+            "while true do pcall(function() while true do end end) end",
+            instructions,
+        )
+        .expect_err("instruction limit didn't occur");
+    assert!(err.find_cause::<ExecutionLimit>().is_some());
+
+    // The same goes for a limit hit inside a Rust callback.
+    let spin = lua
+        .create_function(|lua, ()| lua.load("while true do end").exec())
+        .unwrap();
+    lua.globals().set("spin", spin).unwrap();
+    let err = lua
+        .run_with_limits::<()>("spin()", instructions)
+        .expect_err("instruction limit didn't occur");
+    assert!(err.find_cause::<ExecutionLimit>().is_some());
+
+    let deadline = Limits {
+        deadline: Some(Instant::now() + Duration::from_millis(50)),
+        ..Default::default()
+    };
+    let err = lua
+        .run_with_limits::<()>("while true do end", deadline)
+        .expect_err("deadline didn't occur");
+    assert_eq!(
+        err.find_cause::<ExecutionLimit>(),
+        Some(&ExecutionLimit {
+            kind: LimitKind::Deadline
+        })
+    );
+
+    // Chunks within their limits run as normal, and other errors are left alone.
+    let sum: i64 = lua
+        .run_with_limits(
+            "local n = 0 for i = 1, 100 do n = n + i end return n",
+            instructions,
+        )
+        .unwrap();
+    assert_eq!(sum, 5050);
+    match lua.run_with_limits::<()>("error('oops')", instructions) {
+        Err(Error::RuntimeError(msg)) => assert!(msg.contains("oops")),
+        r => panic!("unexpected result {:?}", r),
+    }
+}
+
+#[test]
+fn run_with_max_depth() {
+    let lua = Lua::new();
+    let limits = Limits {
+        max_depth: Some(50),
+        ..Default::default()
+    };
+
+    let err = lua
+        .run_with_limits::<()>(
+            r#"
+                local function recurse(n)
+                    return 1 + recurse(n + 1)
+                end
+                recurse(1)
+            "#,
+            limits,
+        )
+        .expect_err("depth limit didn't occur");
+    let limit = err
+        .find_cause::<RecursionLimit>()
+        .expect("not a recursion limit error");
+    assert_eq!(limit.depth, 50);
+    assert!(limit.traceback.contains("recurse"), "{}", limit.traceback);
+
+    // Calls through Rust functions count towards the depth too.
+    let f = lua
+        .create_function(|lua, n: u32| lua.globals().get::<_, Function>("f")?.call::<_, ()>(n + 1))
+        .unwrap();
+    lua.globals().set("f", f).unwrap();
+    let err = lua
+        .run_with_limits::<()>("f(1)", limits)
+        .expect_err("depth limit didn't occur");
+    assert!(err.find_cause::<RecursionLimit>().is_some(), "{:?}", err);
+
+    // Deep but bounded recursion within the limit is fine.
+    let n: i64 = lua
+        .run_with_limits(
+            r#"
+                local function sum(n) if n == 0 then return 0 end return n + sum(n - 1) end
+                return sum(40)
+            "#,
+            limits,
+        )
+        .unwrap();
+    assert_eq!(n, 820);
+
+    // Tail calls don't add to the depth, except with Lua 5.1, where this would fail.
+    #[cfg(not(all(rlua_lua51, not(rlua_luajit))))]
+    lua.run_with_limits::<()>(
+        r#"
+            local function count(n) if n == 0 then return end return count(n - 1) end
+            count(100000)
+        "#,
+        limits,
+    )
+    .unwrap();
+}
+
+#[test]
+fn set_max_depth() {
+    let lua = Lua::new();
+    lua.set_max_depth(Some(50)).unwrap();
+    lua.load(
+        r#"
+            function recurse(n)
+                return 1 + recurse(n + 1)
+            end
+            function sum(n) if n == 0 then return 0 end return n + sum(n - 1) end
+        "#,
+    )
+    .exec()
+    .unwrap();
+
+    // Calls from Rust are limited, outside of `run_with_limits`.
+    let recurse: Function = lua.globals().get("recurse").unwrap();
+    let err = recurse
+        .call::<_, ()>(1)
+        .expect_err("depth limit didn't occur");
+    let limit = err
+        .find_cause::<RecursionLimit>()
+        .expect("not a recursion limit error");
+    assert_eq!(limit.depth, 50);
+    assert!(limit.traceback.contains("recurse"), "{}", limit.traceback);
+
+    let sum: Function = lua.globals().get("sum").unwrap();
+    assert_eq!(sum.call::<_, i64>(40).unwrap(), 820);
+
+    // So are coroutines, each counting from its own stack, and scripts run with other limits.
+    let err = lua
+        .load("coroutine.wrap(function() recurse(1) end)()")
+        .exec()
+        .unwrap_err();
+    assert!(err.find_cause::<RecursionLimit>().is_some(), "{:?}", err);
+    let limits = Limits {
+        instructions: Some(1_000_000),
+        ..Default::default()
+    };
+    let err = lua.run_with_limits::<()>("recurse(1)", limits).unwrap_err();
+    assert!(err.find_cause::<RecursionLimit>().is_some(), "{:?}", err);
+
+    // The limit sits on top of other hooks, and can be removed.
+    let calls = Arc::new(Mutex::new(0));
+    let hook_calls = calls.clone();
+    lua.set_hook(HookTriggers::new().on_calls(), move |_, _| {
+        *hook_calls.lock().unwrap() += 1;
+        Ok(())
+    });
+    lua.set_max_depth(Some(50)).unwrap();
+    assert!(recurse.call::<_, ()>(1).is_err());
+    assert!(*calls.lock().unwrap() >= 50);
+    lua.set_max_depth(None).unwrap();
+    assert_eq!(sum.call::<_, i64>(100).unwrap(), 5050);
+
+    // Coroutines which already have the hook follow changes to the limit.
+    lua.set_max_depth(Some(50)).unwrap();
+    let thread = lua.create_thread(sum.clone()).unwrap();
+    lua.set_max_depth(None).unwrap();
+    assert_eq!(thread.resume::<_, i64>(100).unwrap(), 5050);
+}
+
+#[test]
+fn run_with_limits_restores_hook() {
+    let lines = Arc::new(Mutex::new(0));
+    let hook_lines = lines.clone();
+
+    let lua = Lua::new();
+    lua.set_hook(
+        HookTriggers {
+            every_line: true,
+            ..Default::default()
+        },
+        move |_lua, _debug| {
+            *hook_lines.lock().unwrap() += 1;
+            Ok(())
+        },
+    );
+
+    let limits = Limits {
+        instructions: Some(1000),
+        ..Default::default()
+    };
+    assert!(lua
+        .run_with_limits::<()>("while true do end", limits)
+        .is_err());
+    *lines.lock().unwrap() = 0;
+
+    lua.load(
+        r#"
+            local x = 1
+            local y = 2
+        "#,
+    )
+    .exec()
+    .unwrap();
+    assert_eq!(*lines.lock().unwrap(), 2);
+}
+
+#[test]
+fn interrupt_from_thread() {
+    fn assert_send_sync<T: Send + Sync + Clone>() {}
+    assert_send_sync::<InterruptHandle>();
+
+    let lua = Lua::new();
+    let lines = Arc::new(Mutex::new(0));
+    let hook_lines = lines.clone();
+    lua.set_hook(
+        HookTriggers {
+            every_line: true,
+            ..Default::default()
+        },
+        move |_lua, _debug| {
+            *hook_lines.lock().unwrap() += 1;
+            Ok(())
+        },
+    );
+
+    for _ in 0..2 {
+        let handle = lua.interrupt_handle().unwrap();
+        let interrupter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            handle.interrupt();
+        });
+        let err = lua
+            .load("while true do end")
+            .exec()
+            .expect_err("script wasn't interrupted");
+        assert_eq!(err.find_cause::<Interrupted>(), Some(&Interrupted));
+        interrupter.join().unwrap();
+
+        // The state is still usable, with the previous hook in place.
+        *lines.lock().unwrap() = 0;
+        assert_eq!(lua.load("return 1 + 1").eval::<i64>().unwrap(), 2);
+        assert_eq!(*lines.lock().unwrap(), 1);
+    }
+
+    let handle = lua.interrupt_handle().unwrap();
+    drop(lua);
+    handle.interrupt();
+}
+
+fn interrupt_after(handle: &InterruptHandle, delay: Duration) -> thread::JoinHandle<()> {
+    let handle = handle.clone();
+    thread::spawn(move || {
+        thread::sleep(delay);
+        handle.interrupt();
+    })
+}
+
+#[test]
+fn interrupt_coroutine() {
+    let lua = Lua::new();
+    let handle = lua.interrupt_handle().unwrap();
+
+    let interrupter = interrupt_after(&handle, Duration::from_millis(50));
+    let err = lua
+        .load("coroutine.wrap(function() while true do end end)()")
+        .exec()
+        .expect_err("coroutine wasn't interrupted");
+    assert_eq!(err.find_cause::<Interrupted>(), Some(&Interrupted));
+    interrupter.join().unwrap();
+
+    // Threads created from Rust are covered as well.
+    let thread = lua
+        .create_thread(lua.load("while true do end").into_function().unwrap())
+        .unwrap();
+    let interrupter = interrupt_after(&handle, Duration::from_millis(50));
+    let err = thread
+        .resume::<_, ()>(())
+        .expect_err("thread wasn't interrupted");
+    assert_eq!(err.find_cause::<Interrupted>(), Some(&Interrupted));
+    interrupter.join().unwrap();
+}
+
+#[test]
+fn interrupt_through_pcall() {
+    let lua = Lua::new();
+    let handle = lua.interrupt_handle().unwrap();
+
+    for script in [
+        "while true do pcall(function() while true do end end) end",
+        "while true do pcall(coroutine.wrap(function() while true do end end)) end",
+    ] {
+        let interrupter = interrupt_after(&handle, Duration::from_millis(50));
+        let err = lua
+            .load(script)
+            .exec()
+            .expect_err("script wasn't interrupted");
+        assert_eq!(err.find_cause::<Interrupted>(), Some(&Interrupted));
+        interrupter.join().unwrap();
+
+        // Only the interrupted script is stopped.
+        let ok = lua.load("return pcall(function() for i = 1, 10000 do end end)");
+        assert!(ok.eval::<bool>().unwrap());
+    }
+}
+
+#[test]
+fn interrupt_after_remove_hook() {
+    let lua = Lua::new();
+    let handle = lua.interrupt_handle().unwrap();
+
+    // An interrupt made while idle stops the next script.
+    handle.interrupt();
+    lua.remove_hook();
+    assert!(lua.load("for i = 1, 10000 do end").exec().is_ok());
+    let handle = lua.interrupt_handle().unwrap();
+    let err = lua.load("for i = 1, 10000 do end").exec().unwrap_err();
+    assert_eq!(err.find_cause::<Interrupted>(), Some(&Interrupted));
+
+    // And later interrupts still work.
+    for _ in 0..2 {
+        let interrupter = interrupt_after(&handle, Duration::from_millis(50));
+        let err = lua.load("while true do end").exec().unwrap_err();
+        assert_eq!(err.find_cause::<Interrupted>(), Some(&Interrupted));
+        interrupter.join().unwrap();
+        assert!(lua.load("for i = 1, 10000 do end").exec().is_ok());
+    }
+}
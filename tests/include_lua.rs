@@ -0,0 +1,36 @@
+use rlua::{include_lua, Lua};
+
+#[test]
+fn register_bundle() {
+    let bundle = include_lua!("tests/include_lua");
+    let names: Vec<_> = bundle.modules().map(|(name, _)| name).collect();
+    assert_eq!(names, ["greet", "pkg", "pkg.util"]);
+
+    let lua = Lua::new();
+    bundle.register(&lua).unwrap();
+    lua.load(
+        r#"
+            assert(require("greet")("world") == "hello world from greet")
+            assert(require("pkg").util.double(21) == 42)
+            assert(require("pkg.util") == require("pkg").util)
+        "#,
+    )
+    .exec()
+    .unwrap();
+}
+
+#[test]
+fn chunk_names() {
+    let lua = Lua::new();
+    include_lua!("tests/include_lua").register(&lua).unwrap();
+    let err = lua
+        .load(r#"require("pkg.util").double({})"#)
+        .exec()
+        .unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("tests/include_lua/pkg/util.lua:4:"),
+        "{}",
+        err
+    );
+}
@@ -0,0 +1,4 @@
+local name = ...
+return function(who)
+    return "hello " .. who .. " from " .. name
+end
@@ -0,0 +1 @@
+return { util = require("pkg.util") }
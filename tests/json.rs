@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+
+use rlua::modules::json::{self, DecodeError, EncodeError};
+use rlua::{ErrorExt, Function, IntoLuaMulti, Lua, Result, Table, Value};
+
+fn json_lua() -> Lua {
+    let lua = Lua::new();
+    json::register(&lua).unwrap();
+    lua.load("json = require('json')").exec().unwrap();
+    lua
+}
+
+fn call<'lua>(lua: &'lua Lua, name: &str, args: impl IntoLuaMulti<'lua>) -> Result<Value<'lua>> {
+    let json: Table = lua.globals().get("json")?;
+    json.get::<_, Function>(name)?.call(args)
+}
+
+fn encode<'lua>(lua: &'lua Lua, args: impl IntoLuaMulti<'lua>) -> Result<String> {
+    lua.unpack(call(lua, "encode", args)?)
+}
+
+#[test]
+fn test_round_trip() {
+    let lua = json_lua();
+    lua.load(
+        r#"
+            local text = '{"name": "server", "ports": [80, 443], "limits": {"cpu": 0.5},'
+                .. ' "tags": [], "extra": {}, "proxy": null, "enabled": true,'
+                .. ' "escapes": "tab\\t quote\\" \\u00e9 \\ud83d\\ude00 \\/"}'
+            local value = json.decode(text)
+            assert(value.name == "server" and value.enabled == true)
+            assert(#value.ports == 2 and value.ports[2] == 443 and value.limits.cpu == 0.5)
+            assert(value.proxy == json.null and value.proxy ~= nil)
+            assert(next(value.tags) == nil and next(value.extra) == nil)
+            assert(value.escapes == 'tab\t quote" é 😀 /')
+
+            -- Empty arrays stay arrays, and keys come out sorted.
+            assert(json.encode(value) == '{"enabled":true,"escapes":"tab\\t quote\\" é 😀 /",'
+                .. '"extra":{},"limits":{"cpu":0.5},"name":"server","ports":[80,443],'
+                .. '"proxy":null,"tags":[]}')
+
+            assert(json.decode('"\\u0000"') == "\0")
+            assert(json.encode("\0\1\31") == '"\\u0000\\u0001\\u001f"')
+            assert(json.decode(' [ null , [ [ ] ] ] ')[1] == json.null)
+            assert(json.encode(json.decode("[1,[2,[3,{}]]]")) == "[1,[2,[3,{}]]]")
+        "#,
+    )
+    .exec()
+    .unwrap();
+
+    // Values converted from Rust encode as expected, and decoded values convert back.
+    assert_eq!(encode(&lua, vec![1, 2, 3]).unwrap(), "[1,2,3]");
+    let mut map = HashMap::new();
+    map.insert("b", vec![true]);
+    map.insert("a", vec![false, true]);
+    assert_eq!(
+        encode(&lua, map).unwrap(),
+        r#"{"a":[false,true],"b":[true]}"#
+    );
+    assert_eq!(encode(&lua, "text").unwrap(), r#""text""#);
+    assert_eq!(encode(&lua, ()).unwrap(), "null");
+
+    let value = call(&lua, "decode", r#"{"a": [1, 2], "b": [3]}"#).unwrap();
+    let map: HashMap<String, Vec<i64>> = lua.unpack(value).unwrap();
+    assert_eq!(map["a"], [1, 2]);
+    assert_eq!(map["b"], [3]);
+    let value = call(&lua, "decode", r#""é\u00e9""#).unwrap();
+    assert_eq!(lua.unpack::<String>(value).unwrap(), "éé");
+}
+
+#[test]
+fn test_numbers() {
+    let lua = json_lua();
+    let decode = |text: &str| call(&lua, "decode", text).unwrap();
+    assert_eq!(decode("42"), Value::Integer(42));
+    assert_eq!(decode("-7"), Value::Integer(-7));
+    assert_eq!(decode("0.25"), Value::Number(0.25));
+    assert_eq!(decode("-2.5e-3"), Value::Number(-2.5e-3));
+
+    for (value, text) in [
+        (Value::Integer(-12), "-12"),
+        (Value::Number(0.1), "0.1"),
+        (Value::Number(1e300), "1e300"),
+        (Value::Number(-2.5e-7), "-2.5e-7"),
+    ] {
+        assert_eq!(encode(&lua, value).unwrap(), text);
+    }
+    for text in ["0", "-1", "0.5", "1e300", "123456789"] {
+        assert_eq!(encode(&lua, decode(text)).unwrap(), text);
+    }
+
+    #[cfg(any(rlua_lua53, rlua_lua54))]
+    {
+        // Integers and floats keep their subtypes.
+        assert_eq!(decode("1.0"), Value::Number(1.0));
+        assert_eq!(decode("1E2"), Value::Number(100.0));
+        assert_eq!(encode(&lua, decode("1.0")).unwrap(), "1.0");
+        assert_eq!(decode("9223372036854775807"), Value::Integer(i64::MAX));
+        assert_eq!(
+            decode("9223372036854775808"),
+            Value::Number(9223372036854775808.0)
+        );
+    }
+}
+
+#[test]
+fn test_hints() {
+    let lua = json_lua();
+    lua.load(
+        r#"
+            assert(json.encode({}) == "{}")
+            assert(json.encode(json.array()) == "[]")
+            assert(json.encode(json.array({})) == "[]")
+            assert(json.encode(json.object({10, 20})) == '{"1":10,"2":20}')
+            assert(json.encode({1, json.null, 3}) == "[1,null,3]")
+            assert(json.encode({a = json.null}) == '{"a":null}')
+
+            -- Decoded arrays keep their mark, even once emptied.
+            local value = json.decode('{"list": [1]}')
+            value.list[1] = nil
+            assert(json.encode(value) == '{"list":[]}')
+
+            local t = json.array({1, 2})
+            assert(json.array(t) == t)
+        "#,
+    )
+    .exec()
+    .unwrap();
+
+    for (source, message) in [
+        ("return {1, 2, x = 3}", "both integer and string keys"),
+        (
+            "local t = {} t[1] = 1 t[3] = 3 return t",
+            "an array with holes",
+        ),
+        ("return json.array({x = 1})", "an array with string keys"),
+        ("return {[1.5] = true}", "a table with a number key"),
+        ("return {[true] = 1}", "a table with a boolean key"),
+        ("return {f = print}", "cannot encode a function"),
+        ("return 0/0", "cannot encode the number NaN"),
+        ("return {-math.huge}", "cannot encode the number -inf"),
+        ("return '\\255'", "a string which isn't valid UTF-8"),
+        ("local t = {} t.t = t return t", "nested more than 128 deep"),
+    ] {
+        let value: Value = lua.load(source).eval().unwrap();
+        let err = encode(&lua, value).unwrap_err();
+        let cause = err.find_cause::<EncodeError>().expect(source);
+        assert!(cause.to_string().contains(message), "{}: {}", source, cause);
+    }
+}
+
+#[test]
+fn test_pretty() {
+    let lua = json_lua();
+    let value = call(
+        &lua,
+        "decode",
+        r#"{"name": "x", "list": [1, [], {}, {"a": null}], "empty": {}}"#,
+    )
+    .unwrap();
+    let options = lua.create_table().unwrap();
+    options.set("pretty", true).unwrap();
+    assert_eq!(
+        encode(&lua, (value.clone(), options.clone())).unwrap(),
+        r#"{
+  "empty": {},
+  "list": [
+    1,
+    [],
+    {},
+    {
+      "a": null
+    }
+  ],
+  "name": "x"
+}"#
+    );
+
+    options.set("indent", "\t").unwrap();
+    assert_eq!(
+        encode(
+            &lua,
+            (call(&lua, "decode", "[[1]]").unwrap(), options.clone())
+        )
+        .unwrap(),
+        "[\n\t[\n\t\t1\n\t]\n]"
+    );
+    options.set("pretty", false).unwrap();
+    options.set("indent", 1).unwrap();
+    assert_eq!(
+        encode(
+            &lua,
+            (
+                call(&lua, "decode", r#"{"a":[]}"#).unwrap(),
+                options.clone()
+            )
+        )
+        .unwrap(),
+        "{\n \"a\": []\n}"
+    );
+    assert_eq!(
+        encode(&lua, (value, lua.create_table().unwrap()))
+            .unwrap()
+            .lines()
+            .count(),
+        1
+    );
+
+    options.set("indent", true).unwrap();
+    assert!(encode(&lua, (1, options.clone())).is_err());
+
+    // Every level repeats the indent, so long ones are refused.
+    for indent in [Value::Integer(17), Value::Integer(-1)] {
+        options.set("indent", indent).unwrap();
+        assert!(encode(&lua, (1, options.clone())).is_err());
+    }
+    options.set("indent", "x".repeat(17)).unwrap();
+    assert!(encode(&lua, (1, options.clone())).is_err());
+    options.set("indent", 16).unwrap();
+    assert_eq!(encode(&lua, ("x", options)).unwrap(), r#""x""#);
+}
+
+#[cfg(not(rlua_luajit))]
+#[test]
+fn test_encode_memory_limit() {
+    let lua = json_lua();
+    lua.load(
+        r#"
+            local deep = {}
+            for i = 1, 100 do deep = { deep } end
+            wide = {}
+            for i = 1, 2000 do wide[i] = deep end
+        "#,
+    )
+    .exec()
+    .unwrap();
+
+    // Pretty printed, the small table would take over 100MB, which count towards the
+    // memory limit as they're written.
+    lua.set_memory_limit(lua.used_memory() + 10_000_000)
+        .unwrap();
+    let err = lua
+        .load("json.encode(wide, { indent = 16 })")
+        .exec()
+        .unwrap_err();
+    assert!(err.to_string().contains("not enough memory"), "{:?}", err);
+
+    let text: String = lua
+        .load("return json.encode({ wide[1], wide[2] }, { indent = 16 })")
+        .eval()
+        .unwrap();
+    assert_eq!(text.lines().count(), 2 * 201 + 2);
+}
+
+#[test]
+fn test_decode_errors() {
+    let lua = json_lua();
+    for (text, message, line, column) in [
+        ("", "unexpected end of input", 1, 1),
+        ("  ", "unexpected end of input", 1, 3),
+        ("[1, 2", "expected ',' or ']'", 1, 6),
+        ("[1, 2,]", "expected a value", 1, 7),
+        ("{\"a\": 1,\n \"b\" 2}", "expected ':'", 2, 6),
+        ("{\"a\": 1,}", "expected a string key", 1, 9),
+        ("{\"a\": 1 \"b\": 2}", "expected ',' or '}'", 1, 9),
+        ("{'a': 1}", "expected a string key", 1, 2),
+        ("[1]\n x", "trailing characters", 2, 2),
+        ("tru", "expected a value", 1, 1),
+        ("nul", "expected a value", 1, 1),
+        ("NaN", "expected a value", 1, 1),
+        ("01", "trailing characters", 1, 2),
+        ("-", "invalid number", 1, 2),
+        ("1.", "invalid number", 1, 3),
+        ("1e+", "invalid number", 1, 4),
+        ("\"abc", "unterminated string", 1, 5),
+        ("\"a\nb\"", "control character in string", 1, 3),
+        ("\"\\x\"", "invalid escape", 1, 3),
+        ("\"\\u12g4\"", "invalid \\u escape", 1, 4),
+        ("\"\\ud800\"", "unpaired surrogate", 1, 2),
+        ("\"\\ud800\\u0041\"", "unpaired surrogate", 1, 2),
+        ("\"\\udc00\"", "unpaired surrogate", 1, 2),
+        ("[\"éé\", x]", "expected a value", 1, 8),
+    ] {
+        let err = call(&lua, "decode", text).unwrap_err();
+        assert_eq!(
+            err.find_cause::<DecodeError>(),
+            Some(&DecodeError {
+                message: message.to_owned(),
+                line,
+                column,
+            }),
+            "{:?}",
+            text
+        );
+    }
+
+    let text = lua.create_string(b"[\"\xc3\xa9\xff\"]").unwrap();
+    let err = call(&lua, "decode", text).unwrap_err();
+    let cause = err.find_cause::<DecodeError>().unwrap();
+    assert_eq!((cause.message.as_str(), cause.column), ("invalid UTF-8", 4));
+
+    let deep = "[".repeat(129);
+    let err = call(&lua, "decode", deep).unwrap_err();
+    let cause = err.find_cause::<DecodeError>().unwrap();
+    assert_eq!(cause.message, "nested more than 128 deep");
+    assert_eq!(cause.column, 129);
+    assert!(call(&lua, "decode", "[".repeat(128) + &"]".repeat(128)).is_ok());
+
+    // Scripts see the position in the error message.
+    lua.load(
+        r#"
+            local ok, err = pcall(json.decode, '{"a": tru}')
+            assert(not ok and tostring(err):find("expected a value at line 1 column 7", 1, true))
+        "#,
+    )
+    .exec()
+    .unwrap();
+}
+
+#[test]
+fn test_module_without_require() {
+    let lua = Lua::new();
+    lua.globals()
+        .set("codec", json::module(&lua).unwrap())
+        .unwrap();
+    let text: String = lua
+        .load("return codec.encode(codec.decode('[true, null]'))")
+        .eval()
+        .unwrap();
+    assert_eq!(text, "[true,null]");
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_values() {
+    use rlua::LuaSerdeExt;
+
+    let lua = json_lua();
+    assert_eq!(lua.null(), call(&lua, "decode", "null").unwrap());
+
+    // Both use the same array mark, so empty arrays survive either conversion.
+    let value = lua
+        .to_value(&(Vec::<i32>::new(), Some(()), None::<i32>))
+        .unwrap();
+    assert_eq!(encode(&lua, value).unwrap(), "[[],null,null]");
+    let value = call(&lua, "decode", r#"{"a": [], "b": null}"#).unwrap();
+    let map: HashMap<String, Option<Vec<i32>>> = lua.from_value(value).unwrap();
+    assert_eq!(map["a"], Some(vec![]));
+    assert_eq!(map["b"], None);
+}
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
-use rlua::{Error, Lua, Nil, RluaCompat, UserData};
+use rlua::memory::{SizeLimitExceeded, SizeLimitKind, SizeLimits};
+use rlua::{Error, ErrorExt, Lua, LuaMemoryExt, Nil, RluaCompat, UserData};
 
 #[cfg(not(rlua_luajit))] // Custom allocators for LuaJIT not available
 #[test]
@@ -35,6 +36,220 @@ fn test_memory_limit() {
     });
 }
 
+#[cfg(not(rlua_luajit))]
+#[test]
+fn test_measure() {
+    let lua = Lua::new();
+    let f = lua
+        .load("local t = {}; for i = 1,10000 do t[i] = i end; return #t")
+        .into_function()
+        .unwrap();
+
+    let (len, stats) = lua.measure(|| f.call::<_, usize>(())).unwrap();
+    assert_eq!(len.unwrap(), 10000);
+    assert!(stats.allocated > 10000, "{:?}", stats);
+    assert!(stats.peak > 10000, "{:?}", stats);
+    assert!(stats.peak <= stats.allocated);
+
+    // Nothing is allocated outside of the call.
+    let (_, stats) = lua.measure(|| ()).unwrap();
+    assert_eq!(stats.allocated, 0);
+}
+
+#[cfg(not(rlua_luajit))]
+#[test]
+fn test_measure_with_quota() {
+    let lua = Lua::new();
+    let f = lua
+        .load("local t = {}; for i = 1,10000 do t[i] = i end")
+        .into_function()
+        .unwrap();
+    let outer_limit = lua.used_memory() + 10_000_000;
+    lua.set_memory_limit(outer_limit).unwrap();
+
+    let (result, stats) = lua
+        .measure_with_quota(10000, || f.call::<_, ()>(()))
+        .unwrap();
+    match result {
+        Err(Error::MemoryError(_)) => {}
+        something_else => panic!("did not trigger memory error: {:?}", something_else),
+    }
+    assert!(stats.peak <= 10000, "{:?}", stats);
+
+    // Quotas nest, each counting the allocations made inside it.
+    let (inner, outer_stats) = lua
+        .measure_with_quota(10_000_000, || {
+            lua.measure_with_quota(10000, || f.call::<_, ()>(()))
+        })
+        .unwrap();
+    let (result, inner_stats) = inner.unwrap();
+    assert!(matches!(result, Err(Error::MemoryError(_))));
+    assert!(outer_stats.allocated >= inner_stats.allocated);
+
+    // The outer limit is left as it was.
+    assert_eq!(lua.set_memory_limit(usize::MAX).unwrap(), outer_limit);
+    f.call::<_, ()>(()).expect("should trigger no memory limit");
+}
+
+#[cfg(rlua_luajit)]
+#[test]
+fn test_measure_not_available() {
+    let lua = Lua::new();
+    assert!(matches!(
+        lua.measure(|| ()),
+        Err(Error::MemoryLimitNotAvailable)
+    ));
+}
+
+#[test]
+fn test_size_limits() {
+    let lua = Lua::new();
+    lua.set_size_limits(SizeLimits {
+        max_string_len: Some(1000),
+        max_table_entries: Some(100),
+    })
+    .unwrap();
+
+    lua.load(
+        r#"
+            assert(#string.rep("ab", 500) == 1000)
+            assert(#("ab"):rep(500) == 1000)
+            assert(string.rep("ab", -1) == "")
+            local t = {}
+            for i = 1, 99 do table.insert(t, 1, "0123456789") end
+            table.insert(t, "")
+            assert(#table.concat(t, "", 1, 100) == 990)
+            assert(#table.concat(t) == 990)
+
+            local ok, err = pcall(string.rep, "x", 1001)
+            assert(not ok and tostring(err):find("string of 1001 bytes exceeds the length limit of 1000"))
+            ok, err = pcall(table.insert, t, "x")
+            assert(not ok and tostring(err):find("table of 101 entries exceeds the limit of 100"))
+            assert(#t == 100)
+
+            -- Errors in the arguments are left to the real functions.
+            assert(not pcall(string.rep))
+            assert(not pcall(table.concat, {{}}))
+        "#,
+    )
+    .exec()
+    .unwrap();
+
+    let size_error = |source: &str| {
+        let err = lua.load(source).exec().unwrap_err();
+        *err.find_cause::<SizeLimitExceeded>()
+            .unwrap_or_else(|| panic!("unexpected error {:?}", err))
+    };
+    let string_error = |size| SizeLimitExceeded {
+        kind: SizeLimitKind::StringLength,
+        limit: 1000,
+        size,
+    };
+    assert_eq!(size_error("string.rep(12, 501)"), string_error(1002));
+    assert_eq!(size_error("string.rep('x', 2^40)"), string_error(1 << 40));
+    #[cfg(any(rlua_lua53, rlua_lua54))]
+    assert_eq!(
+        size_error("string.rep('x', math.maxinteger, string.rep('-', 999))"),
+        string_error(usize::MAX)
+    );
+    #[cfg(not(all(rlua_lua51, not(rlua_luajit))))]
+    assert_eq!(size_error("string.rep('x', 500, '--')"), string_error(1498));
+    assert_eq!(
+        size_error("table.concat({'xxxxx', 'xxxxx'}, string.rep('-', 995))"),
+        string_error(1005)
+    );
+    assert_eq!(
+        size_error(
+            r#"
+                local t = {}
+                for i = 1, 100 do t[i] = "0123456789x" end
+                table.concat(t)
+            "#
+        ),
+        string_error(1001)
+    );
+    #[cfg(any(rlua_lua53, rlua_lua54))]
+    assert_eq!(
+        size_error("table.move({1, 2, 3}, 1, 3, 99, {})"),
+        SizeLimitExceeded {
+            kind: SizeLimitKind::TableEntries,
+            limit: 100,
+            size: 101,
+        }
+    );
+
+    lua.set_size_limits(SizeLimits::default()).unwrap();
+    lua.load("assert(#string.rep('x', 2000) == 2000)")
+        .exec()
+        .unwrap();
+}
+
+#[cfg(not(rlua_luajit))]
+#[test]
+fn test_size_limits_before_memory_limit() {
+    let lua = Lua::new();
+    lua.set_size_limits(SizeLimits {
+        max_string_len: Some(100_000),
+        max_table_entries: Some(10_000),
+    })
+    .unwrap();
+    lua.set_memory_limit(lua.used_memory() + 1_000_000).unwrap();
+
+    // Each of these would run out of memory without the caps.
+    for source in [
+        "string.rep('x', 2^30)",
+        "local s = string.rep('x', 1000) local t = {} for i = 1, 2000 do t[i] = s end table.concat(t)",
+        "local t = {} for i = 1, 2^30 do table.insert(t, i) end",
+    ] {
+        let err = lua.load(source).exec().unwrap_err();
+        assert!(
+            err.find_cause::<SizeLimitExceeded>().is_some(),
+            "{}: {:?}",
+            source,
+            err
+        );
+    }
+
+    // Lua 5.3 reports running out of memory for `string.rep` as a runtime error.
+    lua.set_size_limits(SizeLimits::default()).unwrap();
+    let err = lua.load("string.rep('x', 2^30)").exec().unwrap_err();
+    assert!(err.to_string().contains("not enough memory"), "{:?}", err);
+}
+
+#[cfg(not(rlua_luajit))]
+#[test]
+fn test_size_limits_with_memory_limit() {
+    let lua = Lua::new();
+    lua.set_size_limits(SizeLimits {
+        max_string_len: Some(10_000),
+        max_table_entries: Some(10_000),
+    })
+    .unwrap();
+    lua.set_memory_limit(lua.used_memory() + 1_000_000).unwrap();
+
+    // Neither goes through a library function, so only the memory limit stops them.
+    for source in [
+        "s = 'x' while true do s = s .. s end",
+        "t = {} for i = 1, 2^30 do t[i] = i end",
+    ] {
+        let err = lua.load(source).exec().unwrap_err();
+        assert!(matches!(err, Error::MemoryError(_)), "{:?}", err);
+    }
+    lua.load("s, t = nil, nil collectgarbage()").exec().unwrap();
+
+    // The library functions still fail with the size limits first, which are kept apart
+    // from the app data.
+    lua.set_app_data(SizeLimits::default());
+    let err = lua.load("string.rep('x', 20000)").exec().unwrap_err();
+    assert!(err.find_cause::<SizeLimitExceeded>().is_some(), "{:?}", err);
+
+    // And the memory limit can still be changed.
+    lua.set_memory_limit(0).unwrap();
+    lua.load("local s = string.rep('x', 10000) s = s .. s .. s")
+        .exec()
+        .unwrap();
+}
+
 #[test]
 fn test_gc_control() {
     let lua = Lua::new();
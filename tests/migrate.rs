@@ -0,0 +1,223 @@
+#![cfg(feature = "migrate")]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn write_source(name: &str, source: &str) -> PathBuf {
+    let mut path = std::env::temp_dir();
+    path.push(name);
+    fs::write(&path, source).unwrap();
+    path
+}
+
+fn run_migrate(args: &[&str], path: &PathBuf) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rlua-migrate"))
+        .args(args)
+        .arg(path)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_migrate_rewrites() {
+    let path = write_source(
+        "test_migrate_rewrites.rs",
+        r#"use rlua::{Lua, Result, RluaCompat, ToLuaCompat, Value};
+
+fn convert(lua: &Lua) -> Result<Value> {
+    lua.context(|ctx| 42.to_lua(ctx))
+}
+
+fn main() {
+    Lua::new().context(|lua| {
+        lua.globals().set("x", 1).unwrap();
+    });
+    let lua = rlua::Lua::new();
+    lua.context(|ctx| -> Result<()> {
+        ctx.globals().set("y", 2)?;
+        Ok(())
+    })
+    .unwrap();
+}
+"#,
+    );
+
+    let output = run_migrate(&[], &path);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        r#"use mlua::{Lua, Result, IntoLua, Value};
+
+fn convert(lua: &Lua) -> Result<Value> {
+    { let ctx: &mlua::Lua = &lua; 42.into_lua(ctx) }
+}
+
+fn main() {
+    { let lua: &mlua::Lua = &Lua::new();
+        lua.globals().set("x", 1).unwrap();
+    };
+    let lua = mlua::Lua::new();
+    { let ctx: &mlua::Lua = &lua; (|| -> Result<()> {
+        ctx.globals().set("y", 2)?;
+        Ok(())
+    })() }
+    .unwrap();
+}
+"#
+    );
+}
+
+#[test]
+fn test_migrate_multi_compat() {
+    let path = write_source(
+        "test_migrate_multi_compat.rs",
+        r#"use rlua::{FromLuaMultiCompat, Lua, MultiValue, Result, ToLuaMulti, ToLuaMultiCompat};
+
+fn roundtrip(lua: &Lua, values: MultiValue) -> Result<(i64, i64)> {
+    let values = values.to_lua_multi(lua)?;
+    FromLuaMultiCompat::from_lua_multi(values, lua)
+}
+"#,
+    );
+
+    let output = run_migrate(&[], &path);
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        r#"use mlua::{FromLuaMulti, Lua, MultiValue, Result, IntoLuaMulti};
+
+fn roundtrip(lua: &Lua, values: MultiValue) -> Result<(i64, i64)> {
+    let values = values.into_lua_multi(lua)?;
+    FromLuaMulti::from_lua_multi(values, lua)
+}
+"#
+    );
+}
+
+#[test]
+fn test_migrate_keeps_rlua_only_items() {
+    let source = r#"use rlua::{Context, Lua, RluaCompat, Table};
+
+fn make_table<'lua>(ctx: Context<'lua>) -> Table<'lua> {
+    ctx.create_table().unwrap()
+}
+
+fn main() {
+    let lua = Lua::new();
+    lua.install_load_wrappers().unwrap();
+    lua.context(|ctx| make_table(ctx));
+}
+"#;
+    let path = write_source("test_migrate_keeps_rlua_only_items.rs", source);
+
+    let output = run_migrate(&["--check"], &path);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(fs::read_to_string(&path).unwrap(), source);
+    let listed = String::from_utf8(output.stdout).unwrap();
+    let listed = listed.lines().collect::<Vec<_>>();
+    assert_eq!(listed.len(), 5);
+    assert!(listed[0].ends_with(":1:12: `Context` is only provided by rlua; use `&Lua` instead"));
+    assert!(listed[1].contains(":1:26: `RluaCompat` is only provided by rlua"));
+    assert!(listed[2].ends_with(":3:26: `Context` is only provided by rlua; use `&Lua` instead"));
+    assert!(listed[3].contains(":9:9: `install_load_wrappers` is only provided by rlua"));
+    assert!(listed[4].ends_with(":10:9: `RluaCompat::context` closure can be inlined"));
+
+    let output = run_migrate(&[], &path);
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        r#"use rlua::{Context, Lua, RluaCompat, Table};
+
+fn make_table<'lua>(ctx: Context<'lua>) -> Table<'lua> {
+    ctx.create_table().unwrap()
+}
+
+fn main() {
+    let lua = Lua::new();
+    lua.install_load_wrappers().unwrap();
+    { let ctx: &rlua::Lua = &lua; make_table(ctx) };
+}
+"#
+    );
+    // Only the usages which couldn't be rewritten are listed.
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 4);
+
+    let output = run_migrate(&["--check"], &path);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_migrate_keeps_rlua_modules() {
+    let source = r#"use rlua::sandbox::Sandbox;
+use rlua::Lua;
+
+fn main() {
+    let lua = Lua::new();
+    let sandbox = rlua::sandbox::Sandbox::new().allow("print");
+    Sandbox::new().load(&lua, "x = 1").unwrap().exec().unwrap();
+    sandbox.load(&lua, "print(x)").unwrap().exec().unwrap();
+}
+"#;
+    let path = write_source("test_migrate_keeps_rlua_modules.rs", source);
+
+    let output = run_migrate(&[], &path);
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        source.replace("use rlua::Lua", "use mlua::Lua")
+    );
+    let listed = String::from_utf8(output.stdout).unwrap();
+    let listed = listed.lines().collect::<Vec<_>>();
+    assert_eq!(listed.len(), 2);
+    assert!(listed[0]
+        .ends_with(":1:11: `sandbox` is only provided by rlua; keep the rlua dependency for it"));
+    assert!(listed[1]
+        .ends_with(":6:19: `sandbox` is only provided by rlua; keep the rlua dependency for it"));
+}
+
+#[test]
+fn test_migrate_check_clean() {
+    let path = write_source(
+        "test_migrate_check_clean.rs",
+        "use mlua::Lua;\n\nfn main() {\n    Lua::new();\n}\n",
+    );
+    let output = run_migrate(&["--check"], &path);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_migrate_knows_rlua_items() {
+    // Every module and extension trait exported from src/lib.rs has to be listed.
+    let lib = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/src/lib.rs")).unwrap();
+    let mut names = Vec::new();
+    for line in lib.lines() {
+        if let Some(name) = line.strip_prefix("pub mod ") {
+            if let Some(name) = name.strip_suffix(';') {
+                names.push(name.to_owned());
+            }
+        } else if let Some(path) = line.strip_prefix("pub use ") {
+            if !path.starts_with("mlua::") && !path.contains('{') {
+                if let Some((_, name)) = path.trim_end_matches(';').rsplit_once("::") {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+    }
+    assert!(names.iter().any(|name| name == "sandbox"));
+    assert!(names.iter().any(|name| name == "LuaLimitsExt"));
+
+    let source = names
+        .iter()
+        .map(|name| format!("use rlua::{};\n", name))
+        .collect::<String>();
+    let path = write_source("test_migrate_knows_rlua_items.rs", &source);
+    let output = run_migrate(&["--check"], &path);
+    let listed = String::from_utf8(output.stdout).unwrap();
+    for name in &names {
+        let message = format!("`{}` is only provided by rlua", name);
+        assert!(listed.contains(&message), "{} isn't listed", name);
+    }
+}
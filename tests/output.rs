@@ -0,0 +1,105 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use rlua::output::{Capture, OutputKind};
+use rlua::{Lua, LuaOutputExt};
+
+#[test]
+fn print_and_write() {
+    let lua = Lua::new();
+    let output = Capture::new();
+    lua.set_output(output.clone()).unwrap();
+
+    lua.load(
+        r#"
+            print("a", 1, nil, true, 1.5)
+            print()
+            io.write("x", 2, "y")
+            io.stdout:write("z"):write("!")
+            assert(io.write("") == io.stdout)
+        "#,
+    )
+    .exec()
+    .unwrap();
+    assert_eq!(output.take(), b"a\t1\tnil\ttrue\t1.5\n\nx2yz!");
+
+    // `__tostring` is honoured, and bad arguments to `write` are still errors.
+    lua.load(r#"print(setmetatable({}, { __tostring = function() return "T" end }))"#)
+        .exec()
+        .unwrap();
+    assert_eq!(output.take(), b"T\n");
+    assert!(lua.load("io.write({})").exec().is_err());
+}
+
+#[test]
+fn output_kinds() {
+    let lua = Lua::new();
+    let output = Arc::new(Mutex::new(Vec::new()));
+    let sink_output = output.clone();
+    lua.set_output_fn(move |kind, bytes| {
+        sink_output.lock().unwrap().push((kind, bytes.to_vec()));
+        Ok(())
+    })
+    .unwrap();
+
+    lua.load(r#"print("p") io.write("w1", "w2") io.stdout:write("m")"#)
+        .exec()
+        .unwrap();
+    assert_eq!(
+        *output.lock().unwrap(),
+        vec![
+            (OutputKind::Print, b"p\n".to_vec()),
+            (OutputKind::Write, b"w1w2".to_vec()),
+            (OutputKind::Write, b"m".to_vec()),
+        ]
+    );
+
+    // Setting another sink replaces the first.
+    let second = Capture::new();
+    lua.set_output(second.clone()).unwrap();
+    lua.load(r#"print("again")"#).exec().unwrap();
+    assert_eq!(output.lock().unwrap().len(), 3);
+    assert_eq!(second.take(), b"again\n");
+}
+
+#[test]
+fn byte_strings() {
+    let lua = Lua::new();
+    let output = Capture::new();
+    lua.set_output(output.clone()).unwrap();
+
+    lua.load(r#"print("\255\254") io.write("\0\192")"#)
+        .exec()
+        .unwrap();
+    assert_eq!(output.take(), [0xff, 0xfe, b'\n', 0x00, 0xc0]);
+}
+
+#[test]
+fn other_files_and_errors() {
+    let lua = Lua::new();
+    let output = Capture::new();
+    lua.set_output(output.clone()).unwrap();
+
+    lua.load(
+        r#"
+            local f = io.tmpfile()
+            f:write("file")
+            io.output(f)
+            io.write(" contents")
+            io.output(io.stdout)
+            f:seek("set")
+            assert(f:read("*a") == "file contents")
+            f:close()
+        "#,
+    )
+    .exec()
+    .unwrap();
+    assert_eq!(output.take(), b"");
+
+    lua.set_output_fn(|_, _| Err(io::Error::other("sink failed")))
+        .unwrap();
+    let (ok, err): (Option<bool>, String) = lua.load(r#"return io.write("x")"#).eval().unwrap();
+    assert_eq!(ok, None);
+    assert_eq!(err, "sink failed");
+    lua.load(r#"print("ignored")"#).exec().unwrap();
+}
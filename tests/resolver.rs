@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use rlua::resolver::{DirectoryResolver, MemoryResolver};
+use rlua::{Error, Lua, LuaResolverExt};
+
+fn memory_resolver() -> MemoryResolver {
+    MemoryResolver::new()
+        .file(
+            "greet.lua",
+            "return function(name) return 'hello ' .. name end",
+        )
+        .file(
+            "pkg/init.lua",
+            "return { name = 'pkg', sub = require('pkg.sub') }",
+        )
+        .file("pkg/sub.lua", "return { name = ... }")
+        .file("broken.lua", "return {")
+        .file(
+            "scripts/main.lua",
+            "#!/usr/bin/env lua\nlocal x = ... return (x or 0) + 1",
+        )
+}
+
+#[test]
+fn require_from_memory() {
+    let lua = Lua::new();
+    lua.set_module_resolver(memory_resolver()).unwrap();
+
+    lua.load(
+        r#"
+            assert(require("greet")("world") == "hello world")
+            local pkg = require("pkg")
+            assert(pkg.name == "pkg" and pkg.sub.name == "pkg.sub")
+            assert(require("pkg") == pkg)
+
+            package.preload.preloaded = function() return "preloaded" end
+            assert(require("preloaded") == "preloaded")
+        "#,
+    )
+    .exec()
+    .unwrap();
+
+    match lua.load("require('missing')").exec() {
+        Err(Error::RuntimeError(msg)) => {
+            assert!(msg.contains("no file 'missing.lua'"), "{}", msg);
+            assert!(msg.contains("no file 'missing/init.lua'"), "{}", msg);
+        }
+        r => panic!("unexpected result {:?}", r),
+    }
+    let err = lua.load("require('broken')").exec().unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("error loading module 'broken' from file 'broken.lua':\n\tbroken.lua:1:"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn loadfile_and_dofile() {
+    let mut files = HashMap::new();
+    files.insert("main.lua".to_owned(), b"return 42".to_vec());
+    files.insert(
+        "binary.lua".to_owned(),
+        Lua::new()
+            .load("return 1")
+            .into_function()
+            .unwrap()
+            .dump(false),
+    );
+    let lua = Lua::new();
+    lua.set_module_resolver(MemoryResolver::from(files))
+        .unwrap();
+    lua.load(
+        r#"
+            assert(dofile("main.lua") == 42)
+            assert(loadfile("./main.lua")() == 42)
+
+            local f, err = loadfile("missing.lua")
+            assert(f == nil and err:find("cannot open missing.lua"))
+            assert(not pcall(dofile, "missing.lua"))
+            assert(loadfile() == nil)
+
+            -- Only source code is loaded.
+            assert(loadfile("binary.lua") == nil)
+        "#,
+    )
+    .exec()
+    .unwrap();
+
+    let lua = Lua::new();
+    lua.set_module_resolver(memory_resolver()).unwrap();
+    let n: i64 = lua
+        .load(r#"return loadfile("scripts/main.lua")(1)"#)
+        .eval()
+        .unwrap();
+    assert_eq!(n, 2);
+    let (f, err): (Option<rlua::Function>, String) =
+        lua.load(r#"return loadfile("broken.lua")"#).eval().unwrap();
+    assert!(f.is_none());
+    assert!(err.starts_with("broken.lua:"), "{}", err);
+}
+
+#[test]
+fn require_from_directory() {
+    let lua = Lua::new();
+    lua.set_module_resolver(DirectoryResolver::new("examples/some_directory"))
+        .unwrap();
+    lua.load(
+        r#"
+            local printed
+            local real_print = print
+            print = function(s) printed = s end
+            require("new_module")
+            print = real_print
+            assert(printed == "I got required!")
+
+            assert(not pcall(dofile, "../custom_lua_path.rs"))
+            assert(not pcall(dofile, "/etc/hosts"))
+        "#,
+    )
+    .exec()
+    .unwrap();
+}
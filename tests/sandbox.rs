@@ -0,0 +1,303 @@
+use rlua::sandbox::{self, Sandbox};
+use rlua::{Function, Lua, Table, Value};
+
+fn new_lua() -> Lua {
+    let lua = Lua::new();
+    sandbox::install_string_methods(&lua).unwrap();
+    lua
+}
+
+fn sandbox() -> Sandbox {
+    Sandbox::new()
+        .allow("assert")
+        .allow("type")
+        .allow("pcall")
+        .allow("string.*")
+        .deny("string.dump")
+        .allow("os.time")
+}
+
+#[test]
+fn test_sandbox_allowlist() {
+    let lua = new_lua();
+    sandbox()
+        .load(
+            &lua,
+            r#"
+                assert(string.format("%d", 42) == "42")
+                assert(string.dump == nil)
+                assert(io == nil and require == nil and print == nil)
+                assert(_G == nil and load == nil and getmetatable == nil)
+            "#,
+        )
+        .unwrap()
+        .exec()
+        .unwrap();
+
+    #[cfg(not(feature = "lua-no-oslib"))]
+    sandbox()
+        .load(
+            &lua,
+            r#"
+                assert(type(os.time) == "function")
+                assert(os.execute == nil and os.getenv == nil)
+            "#,
+        )
+        .unwrap()
+        .exec()
+        .unwrap();
+
+    // Names which don't exist are skipped.
+    let env = Sandbox::new()
+        .allow("no_such_global")
+        .allow("no_such_library.*")
+        .create_env(&lua)
+        .unwrap();
+    assert_eq!(env.raw_len(), 0);
+    assert!(env.pairs::<Value, Value>().next().is_none());
+}
+
+#[test]
+fn test_sandbox_globals_untouched() {
+    let lua = new_lua();
+    let sandbox = sandbox().allow("_G");
+    sandbox
+        .load(
+            &lua,
+            r#"
+                x = 1
+                _G.y = 2
+                string.format = nil
+                if os ~= nil then
+                    os.time = nil
+                end
+                assert(_G.x == 1)
+            "#,
+        )
+        .unwrap()
+        .exec()
+        .unwrap();
+
+    let globals = lua.globals();
+    assert_eq!(globals.get::<_, Value>("x").unwrap(), Value::Nil);
+    assert_eq!(globals.get::<_, Value>("y").unwrap(), Value::Nil);
+    let string: Table = globals.get("string").unwrap();
+    assert!(string.get::<_, Function>("format").is_ok());
+    assert!(string.get::<_, Function>("dump").is_ok());
+    #[cfg(not(feature = "lua-no-oslib"))]
+    {
+        let os: Table = globals.get("os").unwrap();
+        assert!(os.get::<_, Function>("time").is_ok());
+    }
+
+    // Every script gets a fresh environment.
+    sandbox
+        .load(&lua, "assert(x == nil and string.format ~= nil)")
+        .unwrap()
+        .exec()
+        .unwrap();
+}
+
+#[test]
+fn test_sandbox_getmetatable() {
+    let lua = new_lua();
+    sandbox()
+        .allow("getmetatable")
+        .allow("setmetatable")
+        .load(
+            &lua,
+            r#"
+                assert(getmetatable("") == nil)
+                assert(not pcall(function() return getmetatable("").__index end))
+
+                local mt = {}
+                assert(getmetatable(setmetatable({}, mt)) == mt)
+            "#,
+        )
+        .unwrap()
+        .exec()
+        .unwrap();
+}
+
+#[test]
+fn test_sandbox_string_methods() {
+    let lua = new_lua();
+    sandbox()
+        .deny("string.rep")
+        .allow("coroutine.*")
+        .allow("load")
+        .load(
+            &lua,
+            r#"
+                assert(("x"):upper() == "X" and ("%d"):format(1) == "1")
+                assert(("").dump == nil and ("x").rep == nil)
+                assert(not pcall(function() return ("x"):rep(10) end))
+                assert(not pcall(function() return ("x"):dump() end))
+
+                -- Functions which don't use globals see the methods of their callers.
+                local function rep(s) return s:rep(2) end
+                assert(not pcall(rep, "x"))
+                local upper, rep = coroutine.wrap(function() return string.upper, ("x").rep end)()
+                assert(upper ~= nil and rep == nil)
+                assert(load("return ('x').rep")() == nil)
+            "#,
+        )
+        .unwrap()
+        .exec()
+        .unwrap();
+
+    #[cfg(not(all(rlua_lua51, not(rlua_luajit))))]
+    sandbox()
+        .deny("string.rep")
+        .allow("load")
+        .load(
+            &lua,
+            "assert(load(\"return ('x').rep\", 'chunk', 't', {})() == nil)",
+        )
+        .unwrap()
+        .exec()
+        .unwrap();
+
+    // Without `string`, strings have no methods at all, while code outside of the sandbox
+    // keeps all of them.
+    Sandbox::new()
+        .allow("assert")
+        .allow("pcall")
+        .load(
+            &lua,
+            "assert(not pcall(function() return ('x'):upper() end))",
+        )
+        .unwrap()
+        .exec()
+        .unwrap();
+    lua.load("assert(('x'):rep(2) == 'xx' and type(('').dump) == 'function')")
+        .exec()
+        .unwrap();
+}
+
+#[test]
+fn test_sandbox_string_methods_without_env() {
+    let lua = new_lua();
+    let sandbox = sandbox().allow("coroutine.*");
+
+    // Coroutines created in the sandbox keep its methods, even for functions which use no
+    // globals.
+    sandbox
+        .load(
+            &lua,
+            r#"
+                assert(coroutine.wrap(function() return ("").dump end)() == nil)
+                assert(coroutine.wrap(function() return ("").upper end)() ~= nil)
+                local co = coroutine.create(function() return ("").dump end)
+                local ok, dump = coroutine.resume(co)
+                assert(ok and dump == nil)
+                local create = coroutine.create
+                co = (function() return create(function() return ("").dump end) end)()
+                ok, dump = coroutine.resume(co)
+                assert(ok and dump == nil)
+            "#,
+        )
+        .unwrap()
+        .exec()
+        .unwrap();
+
+    // Functions called straight from Rust, or run in a coroutine created from Rust, get no
+    // methods unless the sandbox created the coroutine.
+    let dump: Function = sandbox
+        .load(&lua, "return function() return ('').dump end")
+        .unwrap()
+        .eval()
+        .unwrap();
+    assert_eq!(dump.call::<_, Value>(()).unwrap(), Value::Nil);
+    let thread = lua.create_thread(dump.clone()).unwrap();
+    assert_eq!(thread.resume::<_, Value>(()).unwrap(), Value::Nil);
+    let upper: Function = sandbox
+        .load(&lua, "return function() return ('x'):upper() end")
+        .unwrap()
+        .eval()
+        .unwrap();
+    // Every Lua 5.1 function has the sandbox as its environment.
+    #[cfg(rlua_lua51)]
+    assert_eq!(upper.call::<_, String>(()).unwrap(), "X");
+    #[cfg(not(rlua_lua51))]
+    assert!(upper.call::<_, Value>(()).is_err());
+
+    // A function called from a Rust callback sees the methods of the Lua code below it.
+    let call = lua
+        .create_function(|_, f: Function| f.call::<_, Value>(()))
+        .unwrap();
+    let env = sandbox.create_env(&lua).unwrap();
+    env.set("call", call.clone()).unwrap();
+    lua.load("assert(call(function() return ('').dump end) == nil)")
+        .set_environment(env)
+        .exec()
+        .unwrap();
+    lua.globals().set("call", call).unwrap();
+    lua.load("assert(call(function() return ('').dump end) ~= nil)")
+        .exec()
+        .unwrap();
+
+    // Code outside of sandboxes keeps the whole library in its own coroutines.
+    lua.load(
+        r#"
+            assert(coroutine.wrap(function() return ("").dump end)() ~= nil)
+            local ok, dump = coroutine.resume(coroutine.create(function() return ("").dump end))
+            assert(ok and dump ~= nil)
+        "#,
+    )
+    .exec()
+    .unwrap();
+}
+
+#[test]
+fn test_sandbox_needs_string_methods() {
+    let lua = Lua::new();
+    assert!(sandbox().create_env(&lua).is_err());
+    sandbox::install_string_methods(&lua).unwrap();
+    sandbox::install_string_methods(&lua).unwrap();
+    assert!(sandbox().create_env(&lua).is_ok());
+}
+
+#[test]
+fn test_sandbox_load() {
+    let lua = new_lua();
+    let env = sandbox().allow("load").create_env(&lua).unwrap();
+    let dumped = lua.load("return 42").into_function().unwrap().dump(false);
+    env.set("binchunk", lua.create_string(&dumped).unwrap())
+        .unwrap();
+    lua.load(
+        r#"
+            -- Chunks see the sandbox environment rather than the real globals.
+            sandbox_global = true
+            assert(load("return io")() == nil)
+            assert(load("return sandbox_global")() == true)
+            assert(load("return string.dump")() == nil)
+
+            -- Bytecode is refused.
+            local func, err = load(binchunk)
+            assert(func == nil and type(err) == "string")
+        "#,
+    )
+    .set_environment(env.clone())
+    .exec()
+    .unwrap();
+
+    #[cfg(not(all(rlua_lua51, not(rlua_luajit))))]
+    lua.load(
+        r#"
+            -- A custom environment only holds what the script put in it.
+            local custom = {}
+            assert(load("return os, string, sandbox_global", "chunk", "t", custom)() == nil)
+            load("x = 1", "chunk", "t", custom)()
+            assert(custom.x == 1 and x == nil)
+            assert(load(binchunk, "chunk", "b", custom) == nil)
+            assert(load(binchunk, "chunk", "bt", custom) == nil)
+        "#,
+    )
+    .set_environment(env)
+    .exec()
+    .unwrap();
+
+    // The sandbox itself only loads source.
+    assert!(sandbox().load(&lua, &dumped).unwrap().exec().is_err());
+}
@@ -1,7 +1,9 @@
 use std::cell::Cell;
 use std::rc::Rc;
 
-use rlua::{Error, Function, Lua, MetaMethod, RluaCompat, String, UserData, UserDataMethods};
+use rlua::{
+    Error, Function, Lua, MetaMethod, RluaCompat, ScopeCompat, String, UserData, UserDataMethods,
+};
 
 #[test]
 fn scope_func() {
@@ -68,6 +70,42 @@ fn scope_drop() {
     });
 }
 
+#[test]
+fn scope_static_userdata() {
+    struct MyUserdata(Rc<Cell<i64>>);
+    impl UserData for MyUserdata {
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("inc", |_, data, ()| {
+                data.0.set(data.0.get() + 1);
+                Ok(())
+            });
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let rc = Rc::new(Cell::new(0));
+
+        lua.scope(|scope| {
+            let ud = scope
+                .create_static_userdata(MyUserdata(rc.clone()))
+                .unwrap();
+            assert!(ud.is::<MyUserdata>());
+            lua.globals().set("test", ud).unwrap();
+            lua.load("test:inc() test:inc()").exec().unwrap();
+            assert_eq!(Rc::strong_count(&rc), 2);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(rc.get(), 2);
+        assert_eq!(Rc::strong_count(&rc), 1);
+
+        match lua.load("test:inc()").exec() {
+            Err(Error::CallbackError { .. }) => {}
+            r => panic!("improper return for destructed userdata: {:?}", r),
+        };
+    });
+}
+
 #[test]
 fn scope_capture() {
     let lua = Lua::new();
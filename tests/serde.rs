@@ -0,0 +1,270 @@
+#![cfg(feature = "serde")]
+
+use std::collections::BTreeMap;
+
+use bstr::BString;
+use rlua::{Lua, LuaSerdeExt, Result, SerializeOptions, Table, Value};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+// Converts a value to Lua and back, passing it through a global so that it really ends up
+// in the Lua state.
+fn round_trip<T: Serialize + DeserializeOwned>(lua: &Lua, value: &T) -> Result<T> {
+    lua.globals().set("value", lua.to_value(value)?)?;
+    lua.from_value(lua.globals().get("value")?)
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Config {
+    name: String,
+    enabled: bool,
+    ports: Vec<u16>,
+    limits: BTreeMap<String, Limit>,
+    matrix: Vec<Vec<i32>>,
+    mode: Mode,
+    pair: (i32, String),
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Limit {
+    min: i32,
+    max: Option<i32>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Mode {
+    Off,
+    Fixed(u32),
+    Range { low: u32, high: u32 },
+}
+
+fn config() -> Config {
+    let mut limits = BTreeMap::new();
+    limits.insert(
+        "cpu".to_owned(),
+        Limit {
+            min: 1,
+            max: Some(4),
+        },
+    );
+    limits.insert("memory".to_owned(), Limit { min: 64, max: None });
+    Config {
+        name: "server".to_owned(),
+        enabled: true,
+        ports: vec![80, 443],
+        limits,
+        matrix: vec![vec![1, 2], vec![], vec![3]],
+        mode: Mode::Range { low: 1, high: 10 },
+        pair: (-1, "x".to_owned()),
+    }
+}
+
+#[test]
+fn test_nested_tables() {
+    let lua = Lua::new();
+    assert_eq!(round_trip(&lua, &config()).unwrap(), config());
+    for mode in [Mode::Off, Mode::Fixed(7)] {
+        assert_eq!(round_trip(&lua, &mode).unwrap(), mode);
+    }
+
+    lua.globals().set("null", lua.null()).unwrap();
+    lua.globals()
+        .set("value", lua.to_value(&config()).unwrap())
+        .unwrap();
+    lua.load(
+        r#"
+            assert(value.name == "server" and value.enabled == true)
+            assert(value.limits.cpu.max == 4 and value.limits.memory.max == null)
+            assert(#value.matrix == 3 and value.matrix[1][2] == 2 and #value.matrix[2] == 0)
+            assert(value.mode.Range.high == 10)
+            assert(value.pair[1] == -1 and value.pair[2] == "x")
+        "#,
+    )
+    .exec()
+    .unwrap();
+
+    // Sequences are marked, so that empty ones stay sequences.
+    let value: Table = lua.globals().get("value").unwrap();
+    let metatable = |key: &str| value.get::<_, Table>(key).unwrap().get_metatable();
+    assert_eq!(metatable("ports"), Some(lua.array_metatable()));
+    assert_eq!(metatable("limits"), None);
+    let matrix: Vec<Table> = value.get("matrix").unwrap();
+    assert_eq!(matrix[1].get_metatable(), Some(lua.array_metatable()));
+
+    // Tables written in Lua deserialize as well.
+    let config: Config = lua
+        .from_value(
+            lua.load(
+                r#"
+                    return {
+                        name = "server",
+                        enabled = true,
+                        ports = {80, 443},
+                        limits = {
+                            cpu = {min = 1, max = 4},
+                            memory = {min = 64},
+                        },
+                        matrix = {{1, 2}, {}, {3}},
+                        mode = {Range = {low = 1, high = 10}},
+                        pair = {-1, "x"},
+                    }
+                "#,
+            )
+            .eval()
+            .unwrap(),
+        )
+        .unwrap();
+    assert_eq!(config, self::config());
+}
+
+#[test]
+fn test_arrays_with_holes() {
+    let lua = Lua::new();
+
+    // Holes in Rust sequences become `null`, keeping the later elements.
+    let holes = vec![Some(1), None, Some(3), None];
+    assert_eq!(round_trip(&lua, &holes).unwrap(), holes);
+    lua.globals().set("null", lua.null()).unwrap();
+    lua.load("assert(#value == 4 and value[2] == null and value[4] == null)")
+        .exec()
+        .unwrap();
+
+    // A `nil` in a Lua table ends the sequence.
+    for source in [
+        "return {1, nil, 3}",
+        "local t = {} t[1] = 1 t[3] = 3 return t",
+    ] {
+        let value: Value = lua.load(source).eval().unwrap();
+        assert_eq!(
+            lua.from_value::<Vec<Option<i32>>>(value).unwrap(),
+            [Some(1)]
+        );
+    }
+
+    let empty: Vec<i32> = Vec::new();
+    assert_eq!(round_trip(&lua, &empty).unwrap(), empty);
+    let value: Value = lua.load("return {}").eval().unwrap();
+    assert_eq!(lua.from_value::<Vec<i32>>(value).unwrap(), empty);
+}
+
+#[test]
+fn test_null() {
+    let lua = Lua::new();
+    lua.globals().set("null", lua.null()).unwrap();
+
+    let mut map = BTreeMap::new();
+    map.insert("set".to_owned(), Some(1));
+    map.insert("unset".to_owned(), None);
+    assert_eq!(round_trip(&lua, &map).unwrap(), map);
+    assert_eq!(round_trip(&lua, &()).unwrap(), ());
+    lua.load("assert(value == null)").exec().unwrap();
+
+    // `null` keeps keys which `nil` would drop.
+    let value = lua.to_value(&map).unwrap();
+    lua.globals().set("value", value).unwrap();
+    lua.load("assert(value.unset == null and value.unset ~= nil)")
+        .exec()
+        .unwrap();
+    let options = SerializeOptions::new().serialize_none_to_null(false);
+    let value = lua.to_value_with(&map, options).unwrap();
+    lua.globals().set("value", value).unwrap();
+    lua.load("assert(rawget(value, 'unset') == nil)")
+        .exec()
+        .unwrap();
+
+    // Both `nil` and `null` from Lua deserialize as `None`.
+    let value: Value = lua.load("return {a = null, b = nil}").eval().unwrap();
+    let limit: BTreeMap<String, Option<i32>> = lua.from_value(value).unwrap();
+    assert_eq!(limit.get("a"), Some(&None));
+    assert_eq!(limit.get("b"), None);
+    let limit: Limit = lua
+        .from_value(lua.load("return {min = 1, max = null}").eval().unwrap())
+        .unwrap();
+    assert_eq!(limit, Limit { min: 1, max: None });
+}
+
+#[test]
+fn test_byte_strings() {
+    let lua = Lua::new();
+    let bytes = BString::from(&b"\xff\x00abc"[..]);
+    assert_eq!(round_trip(&lua, &bytes).unwrap(), bytes);
+    lua.load(r#"assert(value == "\255\0abc")"#).exec().unwrap();
+
+    let text = "nul \0 and ünïcode".to_owned();
+    assert_eq!(round_trip(&lua, &text).unwrap(), text);
+
+    // Strings which aren't UTF-8 can't become a `String`.
+    let value: Value = lua.load(r#"return "\255""#).eval().unwrap();
+    assert!(lua.from_value::<String>(value.clone()).is_err());
+    assert_eq!(
+        lua.from_value::<BString>(value).unwrap(),
+        BString::from(&b"\xff"[..])
+    );
+}
+
+#[test]
+fn test_numbers() {
+    let lua = Lua::new();
+
+    // Integers round-trip exactly within the range of a double on every version.
+    for n in [
+        0i64,
+        -1,
+        i32::MIN.into(),
+        i32::MAX.into(),
+        1 << 53,
+        -(1 << 53),
+    ] {
+        assert_eq!(round_trip(&lua, &n).unwrap(), n);
+    }
+    for x in [
+        0.5f64,
+        -1.25,
+        1e300,
+        f64::MAX,
+        f64::MIN,
+        f64::EPSILON,
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+    ] {
+        assert_eq!(round_trip(&lua, &x).unwrap(), x);
+    }
+    assert!(round_trip(&lua, &f64::NAN).unwrap().is_nan());
+    assert_eq!(round_trip(&lua, &1.0f64).unwrap(), 1.0);
+
+    // `u64` values beyond `i64::MAX` become floats, which don't deserialize as integers.
+    assert!(round_trip(&lua, &u64::MAX).is_err());
+
+    #[cfg(any(rlua_lua53, rlua_lua54))]
+    {
+        for n in [i64::MIN, i64::MAX, (1 << 53) + 1] {
+            assert_eq!(round_trip(&lua, &n).unwrap(), n);
+        }
+        for x in [-0.0f64, f64::MIN_POSITIVE, 5e-324] {
+            let back = round_trip(&lua, &x).unwrap();
+            assert_eq!(back.to_bits(), x.to_bits());
+        }
+
+        // Integers and floats are distinct.
+        let value: Value = lua.load("return 3.0").eval().unwrap();
+        assert!(lua.from_value::<i64>(value.clone()).is_err());
+        assert_eq!(lua.from_value::<f64>(value).unwrap(), 3.0);
+    }
+
+    // Lua 5.1 and LuaJIT only have doubles, and whole numbers read back from Lua are
+    // integers, which loses the sign of zero and rounds values smaller than
+    // `f64::EPSILON` to zero.
+    #[cfg(rlua_lua51)]
+    {
+        assert_eq!(round_trip(&lua, &i64::MIN).unwrap(), i64::MIN);
+        assert!(round_trip(&lua, &i64::MAX).is_err());
+        assert_eq!(round_trip(&lua, &((1i64 << 53) + 1)).unwrap(), 1 << 53);
+        for x in [-0.0f64, f64::MIN_POSITIVE, 5e-324] {
+            assert_eq!(round_trip(&lua, &x).unwrap().to_bits(), 0);
+        }
+
+        let value: Value = lua.load("return 3.0").eval().unwrap();
+        assert_eq!(value, Value::Integer(3));
+        assert_eq!(lua.from_value::<i64>(value).unwrap(), 3);
+    }
+}
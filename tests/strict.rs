@@ -0,0 +1,17 @@
+#![cfg(feature = "strict")]
+
+use rlua::prelude::*;
+use rlua::{Lua, Result, Table};
+
+#[test]
+fn test_strict_reexport() -> Result<()> {
+    let lua = Lua::new();
+    let globals = lua.globals();
+    globals.set("double", lua.create_function(|_, x: i64| Ok(x * 2))?)?;
+    assert_eq!(lua.load("return double(21)").eval::<i64>()?, 42);
+
+    let table: Table = lua.load("return { 1, 2, 3 }").eval()?;
+    assert_eq!(table.raw_len(), 3);
+    assert_eq!(7.into_lua(&lua)?, LuaValue::Integer(7));
+    Ok(())
+}
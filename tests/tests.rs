@@ -5,8 +5,8 @@ use std::sync::Arc;
 use std::{error, f32, f64, fmt};
 
 use rlua::{
-    Error, ExternalError, Function, Lua, LuaOptions, Nil, Result, RluaCompat, StdLib, String,
-    Table, UserData, Value, Variadic,
+    ChunkCompat, ChunkMode, Context, Error, ExternalError, Function, InitFlags, Lua, LuaOptions,
+    Nil, Result, RluaCompat, StdLib, String, Table, UserData, Value, Variadic,
 };
 
 #[test]
@@ -360,24 +360,14 @@ fn test_error() {
 }
 
 // Test that skipping the pcall/xpcall wrappers works.
-// TODO: The only way to test that the behaviour is correct is to
-// cause it to abort().
-/*
 #[test]
 fn test_error_nopcall_wrap() {
-    match catch_unwind(|| -> Result<()> {
+    fn run_rust_panic(flags: InitFlags, lua_code: &str) -> Result<bool> {
         unsafe {
-            Lua::unsafe_new_with_flags(StdLib::ALL, InitFlags::DEFAULT - InitFlags::PCALL_WRAPPERS).context(|lua| {
+            Lua::unsafe_new_with_flags(StdLib::ALL, flags).context(|lua| {
                 let globals = lua.globals();
 
-                lua.load(
-                    r#"
-                        function rust_panic()
-                            pcall(function () rust_panic_function() end)
-                        end
-                    "#,
-                )
-                .exec()?;
+                lua.load(lua_code).exec()?;
                 let rust_panic_function = lua
                     .create_function(|_, ()| -> Result<()> { panic!("test_panic") })
                     .unwrap();
@@ -385,53 +375,44 @@ fn test_error_nopcall_wrap() {
 
                 let rust_panic = globals.get::<_, Function>("rust_panic")?;
 
-                rust_panic.call::<_, ()>(())
+                rust_panic.call::<_, bool>(())
             })
         }
-    }) {
-        Ok(Ok(_)) => panic!("no panic was detected, pcall caught it!"),
-        Ok(Err(e)) => panic!("error during panic test {:?}", e),
-        Err(p) => assert!(*p.downcast::<&str>().unwrap() == "test_panic"),
-    };
-
-    match catch_unwind(|| -> Result<()> {
-        unsafe {
-            Lua::unsafe_new_with_flags(StdLib::ALL, InitFlags::DEFAULT - InitFlags::PCALL_WRAPPERS).context(|lua| {
-                let globals = lua.globals();
-
-                lua.load(
-                    r#"
-                        function rust_panic()
-                            xpcall(function() rust_panic_function() end, function() end)
-                        end
-                    "#,
-                )
-                .exec()?;
-                let rust_panic_function = lua
-                    .create_function(|_, ()| -> Result<()> { panic!("test_panic") })
-                    .unwrap();
-                globals.set("rust_panic_function", rust_panic_function)?;
+    }
 
-                let rust_panic = globals.get::<_, Function>("rust_panic")?;
+    for lua_code in [
+        r#"
+            function rust_panic()
+                return pcall(function () rust_panic_function() end)
+            end
+        "#,
+        r#"
+            function rust_panic()
+                return xpcall(function() rust_panic_function() end, function() end)
+            end
+        "#,
+    ] {
+        match catch_unwind(|| run_rust_panic(InitFlags::DEFAULT, lua_code)) {
+            Ok(Ok(_)) => panic!("no panic was detected, pcall caught it!"),
+            Ok(Err(e)) => panic!("error during panic test {:?}", e),
+            Err(p) => assert!(*p.downcast::<&str>().unwrap() == "test_panic"),
+        };
 
-                rust_panic.call::<_, ()>(())
-            })
-        }
-    }) {
-        Ok(Ok(_)) => panic!("no panic was detected, xpcall caught it!"),
-        Ok(Err(e)) => panic!("error during panic test {:?}", e),
-        Err(p) => assert!(*p.downcast::<&str>().unwrap() == "test_panic"),
-    };
+        match catch_unwind(|| {
+            run_rust_panic(InitFlags::DEFAULT - InitFlags::PCALL_WRAPPERS, lua_code)
+        }) {
+            Ok(Ok(ok)) => assert!(!ok),
+            Ok(Err(e)) => panic!("error during panic test {:?}", e),
+            Err(_) => panic!("panic was not caught without the pcall wrappers"),
+        };
+    }
 }
-*/
 
-// The following tests are no longer relevant now that `rlua` is backed by `mlua`
-// which takes slightly different decisions on loading unsafe code; it is up to
-// the user to prevent loading of Lua bytecode if required.
-/*
 #[test]
 fn test_load_wrappers() {
-    Lua::new().context(|lua| {
+    let lua = Lua::new();
+    lua.install_load_wrappers().unwrap();
+    lua.context(|lua| {
         let globals = lua.globals();
         lua.load(
             r#"
@@ -488,7 +469,6 @@ fn test_load_wrappers() {
         assert_eq!(globals.get::<_, u32>("x").unwrap(), 5);
     });
 }
-*/
 
 #[test]
 fn test_no_load_wrappers() {
@@ -546,15 +526,14 @@ fn test_no_load_wrappers() {
     };
 }
 
-// The following tests are no longer relevant now that `rlua` is backed by `mlua`
-// which takes slightly different decisions on loading unsafe code.
-/*
 #[test]
 fn test_loadfile_wrappers() {
     let mut tmppath = std::env::temp_dir();
     tmppath.push("test_loadfile_wrappers.lua");
 
-    Lua::new().context(|lua| {
+    let lua = Lua::new();
+    lua.install_load_wrappers().unwrap();
+    lua.context(|lua| {
         let globals = lua.globals();
         globals.set("filename", tmppath.to_str().unwrap()).unwrap();
         lua.load(
@@ -602,7 +581,6 @@ fn test_loadfile_wrappers() {
         assert_eq!(globals.get::<_, u32>("x").unwrap(), 5);
     });
 }
-*/
 
 #[test]
 fn test_no_loadfile_wrappers() {
@@ -664,16 +642,14 @@ fn test_no_loadfile_wrappers() {
     };
 }
 
-// The following tests are no longer relevant now that `rlua` is backed by `mlua`
-// which takes slightly different decisions on loading unsafe code; it is up to
-// the user to prevent loading of Lua bytecode if required.
-/*
 #[test]
 fn test_dofile_wrappers() {
     let mut tmppath = std::env::temp_dir();
     tmppath.push("test_dofile_wrappers.lua");
 
-    Lua::new().context(|lua| {
+    let lua = Lua::new();
+    lua.install_load_wrappers().unwrap();
+    lua.context(|lua| {
         let globals = lua.globals();
         globals.set("filename", tmppath.to_str().unwrap()).unwrap();
         lua.load(
@@ -721,7 +697,6 @@ fn test_dofile_wrappers() {
         assert_eq!(globals.get::<_, u32>("x").unwrap(), 5);
     });
 }
-*/
 
 #[test]
 fn test_no_dofile_wrappers() {
@@ -780,7 +755,9 @@ fn test_no_dofile_wrappers() {
 
 #[test]
 fn test_loadstring_wrappers() {
-    Lua::new().context(|lua| {
+    let lua = Lua::new();
+    lua.install_load_wrappers().unwrap();
+    lua.context(|lua| {
         let globals = lua.globals();
         if globals.get::<_, Function>("loadstring").is_err() {
             // Loadstring is not present in Lua 5.4, and only with a
@@ -808,6 +785,16 @@ fn test_loadstring_wrappers() {
         .exec()
         .unwrap();
         assert_eq!(globals.get::<_, u32>("x").unwrap(), 1);
+        lua.load(
+            r#"
+                assert(type(binchunk) == "string")
+                chunk = loadstring(binchunk)
+                assert(chunk == nil)
+            "#,
+        )
+        .exec()
+        .unwrap();
+        assert_eq!(globals.get::<_, u32>("x").unwrap(), 1);
         lua.load(
             r#"
                 local s = "x = x + 4"
@@ -822,6 +809,58 @@ fn test_loadstring_wrappers() {
     });
 }
 
+#[test]
+fn test_context_load_chunk_name() {
+    Lua::new().context(|lua| {
+        let location = format!("{}:{}:", file!(), line!() + 1);
+        let err = lua.load("error('boom')").exec().unwrap_err();
+        assert!(err.to_string().contains(&location), "{}", err);
+    });
+}
+
+#[test]
+fn test_into_function_allow_binary() {
+    let lua = Lua::new();
+    lua.install_load_wrappers().unwrap();
+    lua.context(|lua| {
+        let double = lua
+            .load("local x = ... return x * 2")
+            .into_function()
+            .unwrap();
+
+        for strip in [false, true] {
+            let binchunk = double.dump(strip);
+            let reloaded = unsafe { lua.lua().load(&binchunk).into_function_allow_binary() };
+            assert_eq!(reloaded.unwrap().call::<_, i64>(21).unwrap(), 42);
+
+            // The safe path only accepts source, as in 0.19.
+            assert!(lua.load(&binchunk).into_function().is_err());
+            let reloaded = unsafe { lua.load(&binchunk).into_function_allow_binary() };
+            assert_eq!(reloaded.unwrap().call::<_, i64>(21).unwrap(), 42);
+            let reloaded = unsafe {
+                lua.load(&binchunk)
+                    .set_name("double")
+                    .into_function_allow_binary()
+            };
+            assert_eq!(reloaded.unwrap().call::<_, i64>(21).unwrap(), 42);
+            let text_only = lua.load(&binchunk).set_mode(ChunkMode::Text);
+            assert!(unsafe { text_only.into_function_allow_binary() }.is_err());
+
+            lua.globals()
+                .set("binchunk", lua.create_string(&binchunk).unwrap())
+                .unwrap();
+            assert!(lua
+                .load("return load(binchunk)")
+                .eval::<Option<Function>>()
+                .unwrap()
+                .is_none());
+        }
+
+        let text = unsafe { lua.load("return 1 + 2").into_function_allow_binary() }.unwrap();
+        assert_eq!(text.call::<_, i64>(()).unwrap(), 3);
+    });
+}
+
 #[test]
 fn test_no_loadstring_wrappers() {
     unsafe {
@@ -871,27 +910,25 @@ fn test_no_loadstring_wrappers() {
     };
 }
 
-// The following tests are no longer relevant now that `rlua` is backed by `mlua`
-// which takes slightly different decisions on loading unsafe code.
-/*
 #[test]
 fn test_default_loadlib() {
-    Lua::new().context(|lua| {
-        let globals = lua.globals();
-        let package = globals.get::<_, Table>("package").unwrap();
-        let loadlib = package.get::<_, Function>("loadlib");
-        assert!(loadlib.is_err());
+    unsafe {
+        Lua::unsafe_new_with_flags(StdLib::ALL_SAFE, InitFlags::DEFAULT).context(|lua| {
+            let globals = lua.globals();
+            let package = globals.get::<_, Table>("package").unwrap();
+            let loadlib = package.get::<_, Function>("loadlib");
+            assert!(loadlib.is_err());
 
-        lua.load(
-            r#"
-            assert(#(package.loaders or package.searchers) == 2)
-            "#,
-        )
-        .exec()
-        .unwrap();
-    });
+            lua.load(
+                r#"
+                assert(#(package.loaders or package.searchers) == 2)
+                "#,
+            )
+            .exec()
+            .unwrap();
+        });
+    }
 }
-*/
 
 #[test]
 fn test_no_remove_loadlib() {
@@ -1423,6 +1460,45 @@ fn chunk_env() {
     });
 }
 
+#[test]
+fn context_signatures() {
+    fn make_point<'lua>(ctx: Context<'lua>, x: i32, y: i32) -> Result<Table<'lua>> {
+        let point = ctx.create_table()?;
+        point.set("x", x)?;
+        point.set("y", y)?;
+        Ok(point)
+    }
+
+    Lua::new().context(|ctx| {
+        let make = ctx
+            .create_function(|ctx: Context, (x, y): (i32, i32)| make_point(ctx, x, y))
+            .unwrap();
+        ctx.globals().set("make_point", make).unwrap();
+
+        let point = make_point(ctx, 1, 2).unwrap();
+        ctx.globals().set("point", point).unwrap();
+        ctx.load(
+            r#"
+                local p = make_point(3, 4)
+                assert(p.x == 3 and p.y == 4)
+                assert(point.x == 1 and point.y == 2)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let lua: &Lua = ctx.into();
+        assert_eq!(
+            lua.globals()
+                .get::<_, Table>("point")
+                .unwrap()
+                .len()
+                .unwrap(),
+            0
+        );
+    });
+}
+
 #[cfg(not(rlua_lua51))]
 #[test]
 fn context_thread() {
@@ -1472,12 +1548,23 @@ fn test_no_oslib() {
             .load(
                 r#"
                     assert(os == nil)
+                    assert(package.loaded.os == nil)
                 "#,
             )
             .into_function()
             .unwrap();
         f.call::<_, ()>(()).unwrap();
+
+        lua_ctx.load_from_std_lib(StdLib::OS).unwrap();
+        f.call::<_, ()>(()).unwrap();
     });
+
+    let lua = unsafe { Lua::unsafe_new() };
+    lua.load("assert(os == nil)").exec().unwrap();
+    let lua = Lua::new_with(StdLib::OS, LuaOptions::default()).unwrap();
+    lua.load("assert(os == nil and package == nil)")
+        .exec()
+        .unwrap();
 }
 
 // Test the lua-no-oslib feature
@@ -1497,3 +1584,65 @@ fn test_with_oslib() {
         f.call::<_, ()>(()).unwrap();
     });
 }
+
+// Test the lua-compat-mathlib feature
+#[cfg(feature = "lua-compat-mathlib")]
+#[test]
+fn test_compat_mathlib() {
+    let check = |lua: &Lua| {
+        lua.load(
+            r#"
+                local function close(a, b)
+                    return math.abs(a - b) < 1e-12
+                end
+
+                assert(math.pow(2, 10) == 1024)
+                assert(math.pow(4, 0.5) == 2)
+                assert(close(math.cosh(1), (math.exp(1) + math.exp(-1)) / 2))
+                assert(close(math.sinh(1), (math.exp(1) - math.exp(-1)) / 2))
+                assert(close(math.tanh(1), math.sinh(1) / math.cosh(1)))
+                assert(math.log10(1000) == 3)
+
+                local m, e = math.frexp(8)
+                assert(m == 0.5 and e == 4)
+                m, e = math.frexp(-3)
+                assert(m == -0.75 and e == 2)
+                m, e = math.frexp(0)
+                assert(m == 0 and e == 0)
+                m, e = math.frexp(2^-1070)
+                assert(m == 0.5 and e == -1069)
+
+                assert(math.ldexp(0.5, 4) == 8)
+                assert(math.ldexp(-0.75, 2) == -3)
+                assert(math.ldexp(1, -1074) == 2^-1074)
+                assert(math.ldexp(2^-1074, 2097) == 2^1023)
+                assert(math.ldexp(1, 1024) == math.huge)
+                assert(math.ldexp(1, -1100) == 0)
+            "#,
+        )
+        .exec()
+        .unwrap();
+    };
+
+    // rlua builds the builtin math library without the C compat functions, so these
+    // come from the shim in every new state.
+    #[cfg(any(feature = "builtin-lua53", feature = "builtin-lua54"))]
+    check(&Lua::new());
+
+    let lua = unsafe { Lua::unsafe_new_with_flags(StdLib::ALL, InitFlags::DEFAULT) };
+    check(&lua);
+
+    // Whatever the Lua library provides, `load_compat_mathlib` fills in the shim.
+    let lua = Lua::new();
+    lua.load(
+        r#"
+            for _, name in ipairs({"pow", "cosh", "sinh", "tanh", "log10", "frexp", "ldexp"}) do
+                math[name] = nil
+            end
+        "#,
+    )
+    .exec()
+    .unwrap();
+    lua.load_compat_mathlib().unwrap();
+    check(&lua);
+}
@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use rlua::{
     AnyUserData, ExternalError, Function, Lua, MetaMethod, RluaCompat, String, UserData,
-    UserDataMethods,
+    UserDataFields, UserDataFieldsCompat, UserDataMethods, UserDataMethodsCompat,
 };
 
 #[test]
@@ -295,3 +295,63 @@ fn test_align() {
         assert_eq!(globals.get::<_, MyUserData>("userdata").unwrap().0, 99);
     });
 }
+
+#[test]
+fn test_context_methods() {
+    struct MyUserData(i64);
+
+    impl UserData for MyUserData {
+        fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+            fields.add_context_field_method_get("value", |_, data| Ok(data.0));
+            fields.add_context_field_method_set("value", |_, data, value| {
+                data.0 = value;
+                Ok(())
+            });
+            fields
+                .add_context_meta_field_with("__type_name", |lua| lua.create_string("MyUserData"));
+        }
+
+        fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+            methods.add_method("get_value", |_, data, ()| Ok(data.0));
+            methods.add_context_method("as_table", |lua, data, ()| {
+                let table = lua.create_table()?;
+                table.set("value", data.0)?;
+                Ok(table)
+            });
+            methods.add_context_method_mut("add", |lua, data, n: i64| {
+                data.0 += n;
+                lua.globals().set("last_added", n)
+            });
+            methods.add_context_function("new", |lua, n: i64| lua.create_userdata(MyUserData(n)));
+            methods.add_context_meta_method(MetaMethod::ToString, |lua, data, ()| {
+                lua.create_string(format!("MyUserData({})", data.0))
+            });
+        }
+    }
+
+    Lua::new().context(|lua| {
+        let globals = lua.globals();
+        let userdata = lua.create_userdata(MyUserData(42)).unwrap();
+        let metatable = userdata.get_metatable().unwrap();
+        assert_eq!(
+            metatable.get::<String>("__type_name").unwrap(),
+            "MyUserData"
+        );
+        globals.set("userdata", userdata).unwrap();
+        lua.load(
+            r#"
+                assert(userdata.value == 42)
+                userdata.value = 7
+                assert(userdata:get_value() == 7)
+                userdata:add(3)
+                assert(last_added == 3)
+                assert(userdata:as_table().value == 10)
+                assert(tostring(userdata) == "MyUserData(10)")
+                local other = userdata.new(5)
+                assert(other:get_value() == 5)
+            "#,
+        )
+        .exec()
+        .unwrap();
+    });
+}